@@ -0,0 +1,385 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use p256::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::signature::{Keypair, SignatureEncoding, Signer as _, Verifier as _};
+use rsa::traits::PublicKeyParts;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{Error, Jwk};
+
+/// JWS signing algorithms supported by rc-token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    /// HMAC with SHA-256, shared secret
+    #[serde(rename = "HS256")]
+    Hs256,
+    /// RSASSA-PKCS1-v1_5 with SHA-256
+    #[serde(rename = "RS256")]
+    Rs256,
+    /// ECDSA on P-256 with SHA-256
+    #[serde(rename = "ES256")]
+    Es256,
+    /// Ed25519
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Hs256 => "HS256",
+            Algorithm::Rs256 => "RS256",
+            Algorithm::Es256 => "ES256",
+            Algorithm::EdDsa => "EdDSA",
+        }
+    }
+
+    /// Whether the algorithm uses a key pair, so verifiers only need the public key.
+    pub fn is_asymmetric(&self) -> bool {
+        *self != Algorithm::Hs256
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(Algorithm::Hs256),
+            "RS256" => Ok(Algorithm::Rs256),
+            "ES256" => Ok(Algorithm::Es256),
+            "EdDSA" => Ok(Algorithm::EdDsa),
+            _ => Err(Error::Config(format!("unsupported algorithm `{}`", s))),
+        }
+    }
+}
+
+/// Checks token signatures.
+pub trait Verifier: Send + Sync {
+    fn algorithm(&self) -> Algorithm;
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+
+    /// The public key as a JWK, without `kid`. Shared secrets are never
+    /// exported, so HS256 keys return `None`.
+    fn to_jwk(&self) -> Option<Jwk> {
+        None
+    }
+}
+
+/// Produces token signatures. Every signer can also verify its own tokens.
+pub trait Signer: Verifier {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// HS256 shared secret.
+#[derive(Clone)]
+pub struct HmacKey(Hmac<Sha256>);
+
+impl HmacKey {
+    pub fn new(secret: &[u8]) -> Self {
+        HmacKey(Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts keys of any size"))
+    }
+}
+
+impl Verifier for HmacKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Hs256
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let mut mac = self.0.clone();
+        mac.update(message);
+        mac.verify_slice(signature).is_ok()
+    }
+}
+
+impl Signer for HmacKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut mac = self.0.clone();
+        mac.update(message);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+/// RS256 private key.
+#[derive(Clone)]
+pub struct RsaSigningKey(rsa::pkcs1v15::SigningKey<Sha256>);
+
+/// RS256 public key.
+#[derive(Clone)]
+pub struct RsaVerifyingKey(rsa::pkcs1v15::VerifyingKey<Sha256>);
+
+impl RsaSigningKey {
+    /// Loads a PKCS#8 `PRIVATE KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid RSA private key: {}", err)))?;
+        Ok(RsaSigningKey(rsa::pkcs1v15::SigningKey::new(key)))
+    }
+
+    pub fn verifying_key(&self) -> RsaVerifyingKey {
+        RsaVerifyingKey(self.0.verifying_key())
+    }
+}
+
+impl RsaVerifyingKey {
+    /// Loads a SPKI `PUBLIC KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = rsa::RsaPublicKey::from_public_key_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid RSA public key: {}", err)))?;
+        Ok(RsaVerifyingKey(rsa::pkcs1v15::VerifyingKey::new(key)))
+    }
+
+    /// Builds the key from the big-endian modulus and exponent.
+    pub(crate) fn from_components(n: &[u8], e: &[u8]) -> Result<Self, Error> {
+        let key = rsa::RsaPublicKey::new(
+            rsa::BigUint::from_bytes_be(n),
+            rsa::BigUint::from_bytes_be(e),
+        )
+        .map_err(|err| Error::Config(format!("invalid RSA public key: {}", err)))?;
+        Ok(RsaVerifyingKey(rsa::pkcs1v15::VerifyingKey::new(key)))
+    }
+}
+
+impl Verifier for RsaVerifyingKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Rs256
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        rsa::pkcs1v15::Signature::try_from(signature)
+            .map(|signature| self.0.verify(message, &signature).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        let key: &rsa::RsaPublicKey = self.0.as_ref();
+        Some(Jwk::rsa(&key.n().to_bytes_be(), &key.e().to_bytes_be()))
+    }
+}
+
+impl Verifier for RsaSigningKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Rs256
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.verifying_key().verify(message, signature)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        self.verifying_key().to_jwk()
+    }
+}
+
+impl Signer for RsaSigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self.0.try_sign(message)?.to_vec())
+    }
+}
+
+/// ES256 private key.
+#[derive(Clone)]
+pub struct EcdsaSigningKey(p256::ecdsa::SigningKey);
+
+/// ES256 public key.
+#[derive(Clone)]
+pub struct EcdsaVerifyingKey(p256::ecdsa::VerifyingKey);
+
+impl EcdsaSigningKey {
+    /// Loads a PKCS#8 `PRIVATE KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = p256::ecdsa::SigningKey::from_pkcs8_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid P-256 private key: {}", err)))?;
+        Ok(EcdsaSigningKey(key))
+    }
+
+    pub fn verifying_key(&self) -> EcdsaVerifyingKey {
+        EcdsaVerifyingKey(*self.0.verifying_key())
+    }
+}
+
+impl EcdsaVerifyingKey {
+    /// Loads a SPKI `PUBLIC KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = p256::ecdsa::VerifyingKey::from_public_key_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid P-256 public key: {}", err)))?;
+        Ok(EcdsaVerifyingKey(key))
+    }
+
+    /// Builds the key from the big-endian affine coordinates.
+    pub(crate) fn from_coordinates(x: &[u8], y: &[u8]) -> Result<Self, Error> {
+        // SEC1 未压缩格式: 0x04 || x || y
+        let point = [&[0x04], x, y].concat();
+        let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+            .map_err(|err| Error::Config(format!("invalid P-256 public key: {}", err)))?;
+        Ok(EcdsaVerifyingKey(key))
+    }
+}
+
+impl Verifier for EcdsaVerifyingKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Es256
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        // JWS 使用固定长度的 r || s 编码, 而不是 DER
+        p256::ecdsa::Signature::from_slice(signature)
+            .map(|signature| self.0.verify(message, &signature).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        let point = self.0.to_encoded_point(false);
+        Some(Jwk::p256(point.x()?, point.y()?))
+    }
+}
+
+impl Verifier for EcdsaSigningKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Es256
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.verifying_key().verify(message, signature)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        self.verifying_key().to_jwk()
+    }
+}
+
+impl Signer for EcdsaSigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let signature: p256::ecdsa::Signature = self.0.try_sign(message)?;
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+/// EdDSA (Ed25519) private key.
+#[derive(Clone)]
+pub struct Ed25519SigningKey(ed25519_dalek::SigningKey);
+
+/// EdDSA (Ed25519) public key.
+#[derive(Clone)]
+pub struct Ed25519VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl Ed25519SigningKey {
+    /// Loads a PKCS#8 `PRIVATE KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid Ed25519 private key: {}", err)))?;
+        Ok(Ed25519SigningKey(key))
+    }
+
+    pub fn verifying_key(&self) -> Ed25519VerifyingKey {
+        Ed25519VerifyingKey(self.0.verifying_key())
+    }
+}
+
+impl Ed25519VerifyingKey {
+    /// Loads a SPKI `PUBLIC KEY` PEM.
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let key = ed25519_dalek::VerifyingKey::from_public_key_pem(pem)
+            .map_err(|err| Error::Config(format!("invalid Ed25519 public key: {}", err)))?;
+        Ok(Ed25519VerifyingKey(key))
+    }
+
+    /// Builds the key from its 32 byte encoding.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let key = <[u8; 32]>::try_from(bytes)
+            .ok()
+            .and_then(|bytes| ed25519_dalek::VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or_else(|| Error::Config("invalid Ed25519 public key".into()))?;
+        Ok(Ed25519VerifyingKey(key))
+    }
+}
+
+impl Verifier for Ed25519VerifyingKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::EdDsa
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .map(|signature| self.0.verify_strict(message, &signature).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        Some(Jwk::ed25519(self.0.as_bytes()))
+    }
+}
+
+impl Verifier for Ed25519SigningKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::EdDsa
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.verifying_key().verify(message, signature)
+    }
+
+    fn to_jwk(&self) -> Option<Jwk> {
+        self.verifying_key().to_jwk()
+    }
+}
+
+impl Signer for Ed25519SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self.0.try_sign(message)?.to_vec())
+    }
+}
+
+/// A key loaded from config: private keys can sign, public keys only verify.
+pub(crate) struct LoadedKey {
+    pub signer: Option<Arc<dyn Signer>>,
+    pub verifier: Arc<dyn Verifier>,
+}
+
+impl LoadedKey {
+    fn signing<S: Signer + 'static>(key: S) -> Self {
+        let key = Arc::new(key);
+        LoadedKey {
+            signer: Some(key.clone()),
+            verifier: key,
+        }
+    }
+
+    fn verifying<V: Verifier + 'static>(key: V) -> Self {
+        LoadedKey {
+            signer: None,
+            verifier: Arc::new(key),
+        }
+    }
+
+    /// Loads a key for `algorithm`. For HS256 `material` is the shared secret,
+    /// for the other algorithms it is a PKCS#8 private key or SPKI public key PEM.
+    pub fn load(algorithm: Algorithm, material: &str) -> Result<Self, Error> {
+        let is_private = material.contains("PRIVATE KEY-----");
+        Ok(match (algorithm, is_private) {
+            (Algorithm::Hs256, _) => LoadedKey::signing(HmacKey::new(material.as_bytes())),
+            (Algorithm::Rs256, true) => LoadedKey::signing(RsaSigningKey::from_pem(material)?),
+            (Algorithm::Rs256, false) => LoadedKey::verifying(RsaVerifyingKey::from_pem(material)?),
+            (Algorithm::Es256, true) => LoadedKey::signing(EcdsaSigningKey::from_pem(material)?),
+            (Algorithm::Es256, false) => {
+                LoadedKey::verifying(EcdsaVerifyingKey::from_pem(material)?)
+            }
+            (Algorithm::EdDsa, true) => LoadedKey::signing(Ed25519SigningKey::from_pem(material)?),
+            (Algorithm::EdDsa, false) => {
+                LoadedKey::verifying(Ed25519VerifyingKey::from_pem(material)?)
+            }
+        })
+    }
+}
@@ -0,0 +1,220 @@
+//! A small CBOR (RFC 8949) encoder and decoder, covering what CWT and COSE
+//! need: integers, byte and text strings, arrays, maps, tags and the simple
+//! values. Indefinite length items are not supported.
+
+use crate::Error;
+
+// 嵌套层数上限, 防止恶意 token 导致栈溢出
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    /// Major types 0 and 1, from -2^64 to 2^64 - 1.
+    Integer(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    /// Map entries in encoding order.
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+impl Value {
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Integer(n) if *n >= 0 => write_head(out, 0, *n as u64),
+            Value::Integer(n) => write_head(out, 1, (-1 - *n) as u64),
+            Value::Bytes(bytes) => {
+                write_head(out, 2, bytes.len() as u64);
+                out.extend_from_slice(bytes);
+            }
+            Value::Text(text) => {
+                write_head(out, 3, text.len() as u64);
+                out.extend_from_slice(text.as_bytes());
+            }
+            Value::Array(items) => {
+                write_head(out, 4, items.len() as u64);
+                for item in items {
+                    item.write(out);
+                }
+            }
+            Value::Map(entries) => {
+                write_head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.write(out);
+                    value.write(out);
+                }
+            }
+            Value::Tag(tag, value) => {
+                write_head(out, 6, *tag);
+                value.write(out);
+            }
+            Value::Bool(false) => out.push(0xf4),
+            Value::Bool(true) => out.push(0xf5),
+            Value::Null => out.push(0xf6),
+            Value::Float(float) => {
+                out.push(0xfb);
+                out.extend_from_slice(&float.to_be_bytes());
+            }
+        }
+    }
+
+    /// Decodes a single item which must span all of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Value, Error> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let value = reader.read(0)?;
+        if reader.pos != bytes.len() {
+            return Err(Error::Malformed);
+        }
+        Ok(value)
+    }
+
+    /// Looks up an integer key of a map.
+    pub fn get(&self, key: i128) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| *k == Value::Integer(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+// 使用最短的编码, 保证同样的内容编码结果相同
+fn write_head(out: &mut Vec<u8>, major: u8, arg: u64) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(arg as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&arg.to_be_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(len).ok_or(Error::Malformed)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(Error::Malformed)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, Error> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |n, byte| (n << 8) | *byte as u64))
+    }
+
+    /// A length, which can never exceed the bytes left since every item
+    /// takes at least one byte.
+    fn len(&mut self, arg: u64) -> Result<usize, Error> {
+        let len = usize::try_from(arg).map_err(|_| Error::Malformed)?;
+        if len > self.bytes.len() - self.pos {
+            return Err(Error::Malformed);
+        }
+        Ok(len)
+    }
+
+    fn read(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Malformed);
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+
+        if major == 7 {
+            return match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 => Ok(Value::Null),
+                25 => Ok(Value::Float(half_to_f64(self.uint(2)? as u16))),
+                26 => Ok(Value::Float(f32::from_bits(self.uint(4)? as u32) as f64)),
+                27 => Ok(Value::Float(f64::from_bits(self.uint(8)?))),
+                _ => Err(Error::Malformed),
+            };
+        }
+
+        let arg = match info {
+            0..=23 => info as u64,
+            24 => self.uint(1)?,
+            25 => self.uint(2)?,
+            26 => self.uint(4)?,
+            27 => self.uint(8)?,
+            _ => return Err(Error::Malformed),
+        };
+        match major {
+            0 => Ok(Value::Integer(arg as i128)),
+            1 => Ok(Value::Integer(-1 - arg as i128)),
+            2 => {
+                let len = self.len(arg)?;
+                Ok(Value::Bytes(self.take(len)?.to_vec()))
+            }
+            3 => {
+                let len = self.len(arg)?;
+                let text = std::str::from_utf8(self.take(len)?).map_err(|_| Error::Malformed)?;
+                Ok(Value::Text(text.to_string()))
+            }
+            4 => {
+                let len = self.len(arg)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.read(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            5 => {
+                let len = self.len(arg)?;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let key = self.read(depth + 1)?;
+                    let value = self.read(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Ok(Value::Map(entries))
+            }
+            _ => Ok(Value::Tag(arg, Box::new(self.read(depth + 1)?))),
+        }
+    }
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
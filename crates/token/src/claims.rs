@@ -0,0 +1,192 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, Scopes};
+
+/// Registered JWT claims (RFC 7519 section 4.1), all optional, plus the
+/// registered `scope` (RFC 8693) and `cnf` (RFC 7800) claims.
+///
+/// Times are NumericDate values, i.e. seconds since the unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_audience",
+        deserialize_with = "deserialize_audience"
+    )]
+    pub aud: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// `None` for tokens issued before scopes were introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scopes>,
+    /// Key the token is bound to (RFC 7800), set for DPoP bound tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+/// The `cnf` claim. Only the JWK thumbprint confirmation of RFC 9449 is supported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub jkt: String,
+}
+
+impl RegisteredClaims {
+    /// Issuer and audience stamped on new tokens, from `TOKEN_ISSUER` and
+    /// the comma separated `TOKEN_AUDIENCE`. Both are optional.
+    pub fn from_env() -> Self {
+        RegisteredClaims {
+            iss: dotenvy::var("TOKEN_ISSUER").ok(),
+            aud: dotenvy::var("TOKEN_AUDIENCE")
+                .map(|aud| split_list(&aud))
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+// RFC 7519: `aud` 可以是单个字符串, 也可以是字符串数组
+fn serialize_audience<S: Serializer>(aud: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    match aud {
+        [single] => serializer.serialize_str(single),
+        _ => aud.serialize(serializer),
+    }
+}
+
+fn deserialize_audience<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Audience {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Audience::deserialize(deserializer)? {
+        Audience::One(aud) => vec![aud],
+        Audience::Many(aud) => aud,
+    })
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Options controlling which claims `decode_token` checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validation {
+    /// Reject tokens whose `exp` has passed.
+    pub validate_exp: bool,
+    /// Reject tokens whose `nbf` is still in the future.
+    pub validate_nbf: bool,
+    /// Allowed clock difference in seconds for `exp` and `nbf`.
+    pub leeway: i64,
+    /// Required `iss`, if any.
+    pub issuer: Option<String>,
+    /// Required `aud`, if any. The token must list at least one of them.
+    pub audience: Vec<String>,
+    /// Accept tokens issued before the registered claims were introduced,
+    /// which only carry the custom `e` (expiry) and `n` (nonce) fields and no
+    /// `iss` or `aud`.
+    pub allow_legacy: bool,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation {
+            validate_exp: true,
+            validate_nbf: true,
+            leeway: 0,
+            issuer: None,
+            audience: Vec::new(),
+            allow_legacy: true,
+        }
+    }
+}
+
+impl Validation {
+    /// Loads the validation options from the environment (or `.env`):
+    /// `TOKEN_ISSUER`, `TOKEN_AUDIENCE`, `TOKEN_LEEWAY_SECONDS` and
+    /// `TOKEN_ALLOW_LEGACY` (`true` by default).
+    pub fn from_env() -> Result<Self, Error> {
+        let defaults = RegisteredClaims::from_env();
+        let leeway = match dotenvy::var("TOKEN_LEEWAY_SECONDS") {
+            Ok(leeway) => leeway
+                .parse()
+                .map_err(|_| Error::Config(format!("invalid TOKEN_LEEWAY_SECONDS `{}`", leeway)))?,
+            Err(_) => 0,
+        };
+        let allow_legacy = match dotenvy::var("TOKEN_ALLOW_LEGACY") {
+            Ok(allow) => allow
+                .parse()
+                .map_err(|_| Error::Config(format!("invalid TOKEN_ALLOW_LEGACY `{}`", allow)))?,
+            Err(_) => true,
+        };
+        Ok(Validation {
+            leeway,
+            issuer: defaults.iss,
+            audience: defaults.aud,
+            allow_legacy,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn validate(
+        &self,
+        claims: &RegisteredClaims,
+        legacy: bool,
+        now: i64,
+    ) -> Result<(), Error> {
+        if legacy && !self.allow_legacy {
+            return Err(Error::MissingClaim("exp"));
+        }
+
+        if self.validate_exp {
+            let exp = claims.exp.ok_or(Error::MissingClaim("exp"))?;
+            if now >= exp + self.leeway {
+                return Err(Error::Expired);
+            }
+        }
+
+        if self.validate_nbf {
+            if let Some(nbf) = claims.nbf {
+                if now < nbf - self.leeway {
+                    return Err(Error::NotYetValid);
+                }
+            }
+        }
+
+        // 旧格式的 token 没有 iss 和 aud
+        if legacy {
+            return Ok(());
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(Error::InvalidIssuer);
+            }
+        }
+
+        if !self.audience.is_empty() && !claims.aud.iter().any(|aud| self.audience.contains(aud)) {
+            return Err(Error::InvalidAudience);
+        }
+
+        Ok(())
+    }
+}
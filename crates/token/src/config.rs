@@ -0,0 +1,74 @@
+use chrono::Duration;
+
+use crate::{Error, TokenType};
+
+/// Lifetimes of the tokens issued together at login or refresh.
+///
+/// Load it once with `from_env` and pass it to every issuance path, instead
+/// of handing bare second counts around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenPairConfig {
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+}
+
+impl Default for TokenPairConfig {
+    /// One hour access tokens and 30 day refresh tokens.
+    fn default() -> Self {
+        TokenPairConfig {
+            access_token_ttl: Duration::hours(1),
+            refresh_token_ttl: Duration::days(30),
+        }
+    }
+}
+
+impl TokenPairConfig {
+    pub fn with_access_token_ttl(mut self, ttl: Duration) -> Self {
+        self.access_token_ttl = ttl;
+        self
+    }
+
+    pub fn with_refresh_token_ttl(mut self, ttl: Duration) -> Self {
+        self.refresh_token_ttl = ttl;
+        self
+    }
+
+    /// Loads the lifetimes from `TOKEN_EXPIRY_SECONDS` (access token) and
+    /// `REFRESH_TOKEN_EXPIRY_SECONDS`, falling back to the defaults.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = TokenPairConfig::default();
+        if let Some(ttl) = seconds_from_env("TOKEN_EXPIRY_SECONDS")? {
+            config = config.with_access_token_ttl(ttl);
+        }
+        if let Some(ttl) = seconds_from_env("REFRESH_TOKEN_EXPIRY_SECONDS")? {
+            config = config.with_refresh_token_ttl(ttl);
+        }
+        Ok(config)
+    }
+
+    pub fn access_token_ttl(&self) -> Duration {
+        self.access_token_ttl
+    }
+
+    pub fn refresh_token_ttl(&self) -> Duration {
+        self.refresh_token_ttl
+    }
+
+    pub fn ttl(&self, token_type: TokenType) -> Duration {
+        match token_type {
+            TokenType::AccessToken => self.access_token_ttl,
+            TokenType::RefreshToken => self.refresh_token_ttl,
+            TokenType::OneTime(purpose) => purpose.default_ttl(),
+        }
+    }
+}
+
+fn seconds_from_env(name: &str) -> Result<Option<Duration>, Error> {
+    let Ok(value) = dotenvy::var(name) else {
+        return Ok(None);
+    };
+    match value.parse::<i64>() {
+        Ok(seconds) if seconds > 0 => Ok(Some(Duration::seconds(seconds))),
+        _ => Err(Error::Config(format!("invalid {} `{}`", name, value))),
+    }
+}
@@ -0,0 +1,318 @@
+//! CBOR Web Tokens (RFC 8392), signed as COSE_Sign1 or, for HS256, MACed as
+//! COSE_Mac0 (RFC 9052), and sent as unpadded base64url.
+//!
+//! The claims are the same as in a JWT, converted from their JSON form:
+//! registered claims use the integer keys of RFC 8392 (`scope` and `cnf`
+//! those of RFC 8693 and RFC 8747), the token type is a small integer and
+//! every other claim keeps its name. Decoding converts back, so callers see
+//! the same claims whatever the format.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value as Json};
+
+use crate::algorithm::{Algorithm, Signer, Verifier};
+use crate::cbor::Value;
+use crate::{Error, Purpose, TokenType};
+
+const TAG_MAC0: u64 = 17;
+const TAG_SIGN1: u64 = 18;
+
+// COSE header parameters
+const HEADER_ALG: i128 = 1;
+const HEADER_KID: i128 = 4;
+
+/// JWT claim names and their CWT keys.
+const CLAIM_KEYS: [(&str, i128); 9] = [
+    ("iss", 1),
+    ("sub", 2),
+    ("aud", 3),
+    ("exp", 4),
+    ("nbf", 5),
+    ("iat", 6),
+    ("jti", 7),
+    ("cnf", 8),
+    ("scope", 9),
+];
+
+const TOKEN_TYPE: &str = "t";
+
+// JWT 中 jti 是字符串, CWT 中的 cti 是字节串
+const JTI: &str = "jti";
+
+/// Whether `token` looks like a CWT rather than a JWT or a reference token.
+pub(crate) fn is_cwt(token: &str) -> bool {
+    !token.contains('.') && !crate::is_reference_token(token)
+}
+
+pub(crate) fn encode(
+    kid: &str,
+    claims: &impl Serialize,
+    signer: &dyn Signer,
+) -> Result<String, Error> {
+    let payload = claims_to_cbor(serde_json::to_value(claims)?)?.to_vec();
+    let algorithm = signer.algorithm();
+    let protected = Value::Map(vec![(
+        Value::Integer(HEADER_ALG),
+        Value::Integer(cose_algorithm(algorithm)),
+    )])
+    .to_vec();
+    let unprotected = Value::Map(vec![(
+        Value::Integer(HEADER_KID),
+        Value::Bytes(kid.as_bytes().to_vec()),
+    )]);
+
+    let signature = signer.sign(&to_be_signed(algorithm, &protected, &payload))?;
+    let message = Value::Array(vec![
+        Value::Bytes(protected),
+        unprotected,
+        Value::Bytes(payload),
+        Value::Bytes(signature),
+    ]);
+    let tag = if algorithm == Algorithm::Hs256 {
+        TAG_MAC0
+    } else {
+        TAG_SIGN1
+    };
+    Ok(URL_SAFE_NO_PAD.encode(Value::Tag(tag, Box::new(message)).to_vec()))
+}
+
+/// A COSE message split into its parts, signature not yet checked.
+pub(crate) struct Unverified {
+    pub algorithm: Algorithm,
+    pub kid: Option<String>,
+    tag: u64,
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+pub(crate) fn decode(token: &str) -> Result<Unverified, Error> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(token)
+        .map_err(|_| Error::Malformed)?;
+    let Value::Tag(tag @ (TAG_MAC0 | TAG_SIGN1), message) = Value::decode(&bytes)? else {
+        return Err(Error::Malformed);
+    };
+    let Value::Array(parts) = *message else {
+        return Err(Error::Malformed);
+    };
+    let [Value::Bytes(protected), unprotected, Value::Bytes(payload), Value::Bytes(signature)] =
+        <[Value; 4]>::try_from(parts).map_err(|_| Error::Malformed)?
+    else {
+        return Err(Error::Malformed);
+    };
+
+    let algorithm = match Value::decode(&protected)?.get(HEADER_ALG) {
+        Some(Value::Integer(id)) => algorithm_from_cose(*id).ok_or(Error::Malformed)?,
+        _ => return Err(Error::Malformed),
+    };
+    let kid = match unprotected.get(HEADER_KID) {
+        Some(Value::Bytes(kid)) => {
+            Some(String::from_utf8(kid.clone()).map_err(|_| Error::Malformed)?)
+        }
+        Some(_) => return Err(Error::Malformed),
+        None => None,
+    };
+    Ok(Unverified {
+        algorithm,
+        kid,
+        tag,
+        protected,
+        payload,
+        signature,
+    })
+}
+
+impl Unverified {
+    pub fn verify<C: DeserializeOwned>(&self, verifier: &dyn Verifier) -> Result<C, Error> {
+        // 只接受与 key 对应的算法, MAC 和签名的结构也不能混用
+        let expected_tag = if verifier.algorithm() == Algorithm::Hs256 {
+            TAG_MAC0
+        } else {
+            TAG_SIGN1
+        };
+        if self.algorithm != verifier.algorithm()
+            || self.tag != expected_tag
+            || !verifier.verify(
+                &to_be_signed(self.algorithm, &self.protected, &self.payload),
+                &self.signature,
+            )
+        {
+            return Err(Error::InvalidSignature);
+        }
+        Ok(serde_json::from_value(self.claims()?)?)
+    }
+
+    /// The claims in their JSON form, whether or not the signature is valid.
+    pub fn claims(&self) -> Result<Json, Error> {
+        claims_to_json(Value::decode(&self.payload)?)
+    }
+}
+
+// Sig_structure / MAC_structure, external_aad 为空
+fn to_be_signed(algorithm: Algorithm, protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    let context = if algorithm == Algorithm::Hs256 {
+        "MAC0"
+    } else {
+        "Signature1"
+    };
+    Value::Array(vec![
+        Value::Text(context.to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ])
+    .to_vec()
+}
+
+fn cose_algorithm(algorithm: Algorithm) -> i128 {
+    match algorithm {
+        // HMAC 256/256
+        Algorithm::Hs256 => 5,
+        Algorithm::Rs256 => -257,
+        Algorithm::Es256 => -7,
+        Algorithm::EdDsa => -8,
+    }
+}
+
+fn algorithm_from_cose(id: i128) -> Option<Algorithm> {
+    [
+        Algorithm::Hs256,
+        Algorithm::Rs256,
+        Algorithm::Es256,
+        Algorithm::EdDsa,
+    ]
+    .into_iter()
+    .find(|algorithm| cose_algorithm(*algorithm) == id)
+}
+
+const TOKEN_TYPES: [TokenType; 7] = [
+    TokenType::AccessToken,
+    TokenType::RefreshToken,
+    TokenType::OneTime(Purpose::EmailVerification),
+    TokenType::OneTime(Purpose::PasswordReset),
+    TokenType::OneTime(Purpose::EmailChange),
+    TokenType::OneTime(Purpose::Invite),
+    TokenType::OneTime(Purpose::MfaChallenge),
+];
+
+fn claims_to_cbor(claims: Json) -> Result<Value, Error> {
+    let Json::Object(claims) = claims else {
+        return Err(Error::Malformed);
+    };
+    let mut entries = Vec::with_capacity(claims.len());
+    for (name, value) in claims {
+        let entry = match CLAIM_KEYS.iter().find(|(claim, _)| *claim == name) {
+            Some((_, key)) => {
+                let value = match (name.as_str(), value) {
+                    (JTI, Json::String(jti)) => Value::Bytes(jti.into_bytes()),
+                    (_, value) => json_to_cbor(value),
+                };
+                (Value::Integer(*key), value)
+            }
+            None if name == TOKEN_TYPE => {
+                let token_type: TokenType = serde_json::from_value(value)?;
+                let code = TOKEN_TYPES
+                    .iter()
+                    .position(|t| *t == token_type)
+                    .ok_or(Error::Malformed)?;
+                (Value::Text(name), Value::Integer(code as i128))
+            }
+            None => (Value::Text(name), json_to_cbor(value)),
+        };
+        entries.push(entry);
+    }
+    Ok(Value::Map(entries))
+}
+
+fn claims_to_json(claims: Value) -> Result<Json, Error> {
+    let Value::Map(entries) = claims else {
+        return Err(Error::Malformed);
+    };
+    let mut object = Map::new();
+    for (key, value) in entries {
+        let (name, value) = match key {
+            Value::Integer(key) => {
+                let (name, _) = CLAIM_KEYS
+                    .iter()
+                    .find(|(_, k)| *k == key)
+                    .ok_or(Error::Malformed)?;
+                let value = match (*name, value) {
+                    (JTI, Value::Bytes(jti)) => {
+                        Json::String(String::from_utf8(jti).map_err(|_| Error::Malformed)?)
+                    }
+                    (_, value) => cbor_to_json(value)?,
+                };
+                (name.to_string(), value)
+            }
+            Value::Text(name) if name == TOKEN_TYPE => {
+                let token_type = match value {
+                    Value::Integer(code) => usize::try_from(code)
+                        .ok()
+                        .and_then(|code| TOKEN_TYPES.get(code))
+                        .ok_or(Error::Malformed)?,
+                    _ => return Err(Error::Malformed),
+                };
+                (name, serde_json::to_value(token_type)?)
+            }
+            Value::Text(name) => (name, cbor_to_json(value)?),
+            _ => return Err(Error::Malformed),
+        };
+        object.insert(name, value);
+    }
+    Ok(Json::Object(object))
+}
+
+fn json_to_cbor(value: Json) -> Value {
+    match value {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(n), _, _) => Value::Integer(n as i128),
+            (_, Some(n), _) => Value::Integer(n as i128),
+            (_, _, n) => Value::Float(n.unwrap_or_default()),
+        },
+        Json::String(s) => Value::Text(s),
+        Json::Array(items) => Value::Array(items.into_iter().map(json_to_cbor).collect()),
+        Json::Object(object) => Value::Map(
+            object
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key), json_to_cbor(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn cbor_to_json(value: Value) -> Result<Json, Error> {
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(b),
+        Value::Integer(n) => match (i64::try_from(n), u64::try_from(n)) {
+            (Ok(n), _) => Json::from(n),
+            (_, Ok(n)) => Json::from(n),
+            _ => return Err(Error::Malformed),
+        },
+        Value::Float(n) => Json::Number(Number::from_f64(n).ok_or(Error::Malformed)?),
+        Value::Text(s) => Json::String(s),
+        Value::Array(items) => Json::Array(
+            items
+                .into_iter()
+                .map(cbor_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Map(entries) => {
+            let mut object = Map::new();
+            for (key, value) in entries {
+                let Value::Text(key) = key else {
+                    return Err(Error::Malformed);
+                };
+                object.insert(key, cbor_to_json(value)?);
+            }
+            Json::Object(object)
+        }
+        // 其他类型不会出现在由 JSON 转换而来的 claims 中
+        Value::Bytes(_) | Value::Tag(..) => return Err(Error::Malformed),
+    })
+}
@@ -0,0 +1,256 @@
+//! DPoP proofs (RFC 9449): the client signs every request with its own key
+//! pair, and tokens issued to it carry the key thumbprint in `cnf.jkt`, so a
+//! leaked token is useless without the private key.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::algorithm::Signer;
+use crate::clock::{Clock, SystemClock};
+use crate::{jws, Error, RegisteredClaims};
+
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "mysql")]
+pub use self::mysql::MySqlReplayCache;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisReplayCache;
+
+const PROOF_TYPE: &str = "dpop+jwt";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ath: Option<String>,
+}
+
+/// A verified proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpopProof {
+    /// Thumbprint of the client key, compare with the token's `cnf.jkt`.
+    pub jkt: String,
+    pub jti: String,
+    pub iat: i64,
+}
+
+impl DpopProof {
+    /// Checks that `claims` are bound to the key which signed this proof.
+    pub fn check_binding(&self, claims: &RegisteredClaims) -> Result<(), Error> {
+        match &claims.cnf {
+            Some(cnf) if cnf.jkt == self.jkt => Ok(()),
+            _ => Err(Error::InvalidProof("token is not bound to the proof key")),
+        }
+    }
+}
+
+/// Options for checking proofs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpopValidation {
+    /// How long after its `iat` a proof is accepted, in seconds.
+    pub max_age: i64,
+    /// Allowed clock difference in seconds for proofs from the future.
+    pub leeway: i64,
+}
+
+impl DpopValidation {
+    /// Reads `TOKEN_DPOP_MAX_AGE_SECONDS` (default 300) and shares
+    /// `TOKEN_LEEWAY_SECONDS` with token validation.
+    pub fn from_env() -> Result<Self, Error> {
+        let seconds = |name: &str, default: i64| match dotenvy::var(name) {
+            Ok(value) => value
+                .parse::<i64>()
+                .ok()
+                .filter(|seconds| *seconds >= 0)
+                .ok_or_else(|| Error::Config(format!("invalid {} `{}`", name, value))),
+            Err(_) => Ok(default),
+        };
+        Ok(DpopValidation {
+            max_age: seconds("TOKEN_DPOP_MAX_AGE_SECONDS", 300)?,
+            leeway: seconds("TOKEN_LEEWAY_SECONDS", 0)?,
+        })
+    }
+}
+
+impl Default for DpopValidation {
+    fn default() -> Self {
+        DpopValidation {
+            max_age: 300,
+            leeway: 0,
+        }
+    }
+}
+
+/// Remembers the `jti` of accepted proofs so they cannot be replayed. Also
+/// used to mark one-time tokens as redeemed.
+#[async_trait]
+pub trait ReplayCache: Send + Sync {
+    /// Records `jti` until `expires_at`. Returns `false` if it was already
+    /// recorded; the check and insert must be atomic.
+    async fn insert(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, Error>;
+}
+
+/// Process local cache, for tests and single instance deployments.
+pub struct MemoryReplayCache {
+    seen: Mutex<HashMap<String, DateTime<Utc>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryReplayCache {
+    fn default() -> Self {
+        MemoryReplayCache {
+            seen: Mutex::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl MemoryReplayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the clock entries expire by, e.g. with the `FixedClock` of
+    /// the keyring in tests.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+#[async_trait]
+impl ReplayCache for MemoryReplayCache {
+    async fn insert(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        let mut seen = self.seen.lock().unwrap();
+        let now = self.clock.now();
+        seen.retain(|_, expires_at| *expires_at > now);
+        if seen.contains_key(jti) {
+            return Ok(false);
+        }
+        seen.insert(jti.to_string(), expires_at);
+        Ok(true)
+    }
+}
+
+/// Creates a proof for a request, signed with the client key. `access_token`
+/// is the token sent along, if any; it is hashed into the `ath` claim.
+pub fn create_dpop_proof(
+    key: &dyn Signer,
+    method: &str,
+    url: &str,
+    access_token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    let jwk = key
+        .to_jwk()
+        .ok_or_else(|| Error::Config("DPoP proofs need an asymmetric key".into()))?;
+    let header = jws::Header {
+        alg: key.algorithm(),
+        kid: None,
+        typ: Some(PROOF_TYPE.to_string()),
+        jwk: Some(jwk),
+    };
+    let claims = ProofClaims {
+        jti: textnonce::TextNonce::sized(16).unwrap().to_string(),
+        htm: method.to_string(),
+        htu: url.to_string(),
+        iat: now.timestamp(),
+        ath: access_token.map(access_token_hash),
+    };
+    jws::encode_with_header(&header, &claims, key)
+}
+
+/// Verifies the signature and claims of a proof sent with a `method` request
+/// to `url`, without the replay check (see `verify_dpop_proof`).
+pub fn decode_dpop_proof(
+    proof: &str,
+    method: &str,
+    url: &str,
+    access_token: Option<&str>,
+    now: DateTime<Utc>,
+    validation: &DpopValidation,
+) -> Result<DpopProof, Error> {
+    let proof = jws::decode(proof).map_err(|_| Error::InvalidProof("malformed proof"))?;
+    if proof.header.typ.as_deref() != Some(PROOF_TYPE) {
+        return Err(Error::InvalidProof("typ must be dpop+jwt"));
+    }
+    let jwk = proof
+        .header
+        .jwk
+        .as_ref()
+        .ok_or(Error::InvalidProof("missing jwk header"))?;
+    // verifier 为 None 说明不是受支持的非对称签名公钥
+    let verifier = jwk
+        .verifier()
+        .ok()
+        .flatten()
+        .ok_or(Error::InvalidProof("unsupported jwk"))?;
+    let claims: ProofClaims = proof
+        .verify(verifier.as_ref())
+        .map_err(|_| Error::InvalidProof("invalid signature"))?;
+
+    if !claims.htm.eq_ignore_ascii_case(method) {
+        return Err(Error::InvalidProof("htm does not match the request method"));
+    }
+    if strip_query(&claims.htu) != strip_query(url) {
+        return Err(Error::InvalidProof("htu does not match the request URL"));
+    }
+    let now = now.timestamp();
+    if claims.iat > now + validation.leeway || claims.iat + validation.max_age < now {
+        return Err(Error::InvalidProof("iat is outside the accepted window"));
+    }
+    if let Some(access_token) = access_token {
+        if claims.ath.as_deref() != Some(access_token_hash(access_token).as_str()) {
+            return Err(Error::InvalidProof("ath does not match the access token"));
+        }
+    }
+
+    Ok(DpopProof {
+        jkt: jwk
+            .thumbprint()
+            .map_err(|_| Error::InvalidProof("unsupported jwk"))?,
+        jti: claims.jti,
+        iat: claims.iat,
+    })
+}
+
+/// `decode_dpop_proof` followed by the replay check of the proof `jti`.
+pub async fn verify_dpop_proof(
+    proof: &str,
+    method: &str,
+    url: &str,
+    access_token: Option<&str>,
+    now: DateTime<Utc>,
+    validation: &DpopValidation,
+    cache: &dyn ReplayCache,
+) -> Result<DpopProof, Error> {
+    let proof = decode_dpop_proof(proof, method, url, access_token, now, validation)?;
+    // 超出 max_age 的 proof 本身就会被拒绝, 只需记录到那时为止
+    let expires_at = now + Duration::seconds(validation.max_age + validation.leeway);
+    if !cache.insert(&proof.jti, expires_at).await? {
+        return Err(Error::InvalidProof("jti has been used before"));
+    }
+    Ok(proof)
+}
+
+fn access_token_hash(access_token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()))
+}
+
+// RFC 9449: 比较 htu 时忽略 query 和 fragment
+fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
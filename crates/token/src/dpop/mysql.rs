@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{MySql, Pool};
+
+use super::ReplayCache;
+use crate::Error;
+
+/// Stores the `jti` of accepted DPoP proofs in the `dpop_proofs` table.
+pub struct MySqlReplayCache {
+    pool: Pool<MySql>,
+}
+
+impl MySqlReplayCache {
+    pub fn new(pool: Pool<MySql>) -> Self {
+        MySqlReplayCache { pool }
+    }
+
+    /// Removes entries of proofs which are too old to be accepted anyway.
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM dpop_proofs WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl ReplayCache for MySqlReplayCache {
+    async fn insert(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        // 主键冲突时不插入, 影响行数为 0 说明 jti 已被使用
+        let result = sqlx::query("INSERT IGNORE INTO dpop_proofs (jti, expires_at) VALUES (?, ?)")
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+fn store_error(err: sqlx::Error) -> Error {
+    Error::Store(err.to_string())
+}
@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+
+use super::ReplayCache;
+use crate::Error;
+
+const KEY_PREFIX: &str = "aii_server:token:dpop:";
+
+/// Stores the `jti` of accepted DPoP proofs as redis keys which expire
+/// together with the proof. All requests share one connection, see
+/// `RedisRevocationStore`.
+pub struct RedisReplayCache {
+    connection: ConnectionManager,
+}
+
+impl RedisReplayCache {
+    pub fn new(connection: ConnectionManager) -> Self {
+        RedisReplayCache { connection }
+    }
+
+    /// Connects to the redis server at `url`, e.g. `redis://127.0.0.1/`.
+    pub async fn open(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url).map_err(store_error)?;
+        Ok(RedisReplayCache::new(
+            ConnectionManager::new(client).await.map_err(store_error)?,
+        ))
+    }
+}
+
+#[async_trait]
+impl ReplayCache for RedisReplayCache {
+    async fn insert(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        let ttl = (expires_at - Utc::now()).num_seconds().max(1);
+        let mut conn = self.connection.clone();
+        // SET NX 在 key 已存在时返回 nil
+        let inserted: Option<String> = redis::cmd("SET")
+            .arg(format!("{}{}", KEY_PREFIX, jti))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await
+            .map_err(store_error)?;
+        Ok(inserted.is_some())
+    }
+}
+
+fn store_error(err: redis::RedisError) -> Error {
+    Error::Store(err.to_string())
+}
@@ -1,8 +1,113 @@
+/// Errors of token issuance and verification.
+///
+/// `code` gives a stable machine-readable name for each variant, and
+/// `is_token_error` separates problems with the token presented by a client
+/// from configuration and storage failures on our side.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("malformed token")]
+    Malformed,
+
     #[error(transparent)]
-    Jwt(#[from] jwt::Error),
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    #[error("unable to decrypt token")]
+    Decryption,
+
+    #[error("unknown key id `{0}`")]
+    UnknownKey(String),
 
     #[error("expired")]
     Expired,
+
+    #[error("revoked")]
+    Revoked,
+
+    #[error("wrong token type")]
+    WrongTokenType,
+
+    #[error("token has already been used")]
+    AlreadyUsed,
+
+    #[error("password has changed since the token was issued")]
+    PasswordChanged,
+
+    #[error("not yet valid")]
+    NotYetValid,
+
+    #[error("invalid issuer")]
+    InvalidIssuer,
+
+    #[error("invalid audience")]
+    InvalidAudience,
+
+    #[error("invalid DPoP proof: {0}")]
+    InvalidProof(&'static str),
+
+    #[error("missing claim `{0}`")]
+    MissingClaim(&'static str),
+
+    #[error("invalid token configuration: {0}")]
+    Config(String),
+
+    #[error("keyring has no signing key")]
+    NoSigningKey,
+
+    #[error("keyring has no encryption key")]
+    NoEncryptionKey,
+
+    #[error("unable to load JWKS: {0}")]
+    Jwks(String),
+
+    #[error("token store error: {0}")]
+    Store(String),
+
+    #[error(transparent)]
+    Signature(#[from] rsa::signature::Error),
+}
+
+impl Error {
+    /// Stable snake_case code, safe to return to clients and to match on in
+    /// other services. Codes are never renamed once published.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Malformed | Error::Json(_) => "malformed",
+            Error::InvalidSignature => "invalid_signature",
+            Error::Decryption => "decryption_failed",
+            Error::UnknownKey(_) => "unknown_key",
+            Error::Expired => "expired",
+            Error::Revoked => "revoked",
+            Error::WrongTokenType => "wrong_token_type",
+            Error::AlreadyUsed => "already_used",
+            Error::PasswordChanged => "password_changed",
+            Error::NotYetValid => "not_yet_valid",
+            Error::InvalidIssuer => "invalid_issuer",
+            Error::InvalidAudience => "invalid_audience",
+            Error::InvalidProof(_) => "invalid_dpop_proof",
+            Error::MissingClaim(_) => "missing_claim",
+            Error::Config(_) => "config",
+            Error::NoSigningKey => "no_signing_key",
+            Error::NoEncryptionKey => "no_encryption_key",
+            Error::Jwks(_) => "jwks",
+            Error::Store(_) => "store",
+            Error::Signature(_) => "signing_failed",
+        }
+    }
+
+    /// Whether the token (or DPoP proof) itself was rejected, as opposed to
+    /// a failure which is not the client's fault and should not become a 401.
+    pub fn is_token_error(&self) -> bool {
+        !matches!(
+            self,
+            Error::Config(_)
+                | Error::NoSigningKey
+                | Error::NoEncryptionKey
+                | Error::Jwks(_)
+                | Error::Store(_)
+                | Error::Signature(_)
+        )
+    }
 }
@@ -0,0 +1,57 @@
+//! Reading a token without verifying it, for debugging tools.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::{cwt, is_reference_token, Error, TokenFormat};
+
+/// The readable parts of a token. Nothing here is verified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenParts {
+    pub format: TokenFormat,
+    /// JOSE header, or for CWT the COSE `alg` and `kid`; `None` for
+    /// reference tokens.
+    pub header: Option<Value>,
+    /// Claims of a signed token; encrypted and reference tokens have none
+    /// readable without the key or the store.
+    pub payload: Option<Value>,
+}
+
+/// Splits `token` into its parts without checking signature or claims.
+/// Never base decisions on the result, use `decode_token` for that.
+pub fn inspect_token(token: &str) -> Result<TokenParts, Error> {
+    if is_reference_token(token) {
+        return Ok(TokenParts {
+            format: TokenFormat::Reference,
+            header: None,
+            payload: None,
+        });
+    }
+    if cwt::is_cwt(token) {
+        let token = cwt::decode(token)?;
+        return Ok(TokenParts {
+            format: TokenFormat::Cwt,
+            header: Some(json!({"alg": token.algorithm, "kid": token.kid})),
+            payload: Some(token.claims()?),
+        });
+    }
+    let decode = |part: &str| -> Result<Value, Error> {
+        let part = URL_SAFE_NO_PAD.decode(part).map_err(|_| Error::Malformed)?;
+        Ok(serde_json::from_slice(&part)?)
+    };
+    let parts: Vec<&str> = token.split('.').collect();
+    match parts[..] {
+        [header, payload, _] => Ok(TokenParts {
+            format: TokenFormat::Signed,
+            header: Some(decode(header)?),
+            payload: Some(decode(payload)?),
+        }),
+        [header, _, _, _, _] => Ok(TokenParts {
+            format: TokenFormat::Encrypted,
+            header: Some(decode(header)?),
+            payload: None,
+        }),
+        _ => Err(Error::Malformed),
+    }
+}
@@ -0,0 +1,140 @@
+//! Compact JWE serialization with direct key agreement and AES-256-GCM
+//! (`alg: dir`, `enc: A256GCM`), for tokens whose payload clients must not read.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::Error;
+
+const ALG: &str = "dir";
+const ENC: &str = "A256GCM";
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// 256 bit AES-GCM key encrypting token payloads.
+#[derive(Clone)]
+pub struct EncryptionKey(Aes256Gcm);
+
+impl EncryptionKey {
+    /// Creates the key from 32 raw bytes.
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        Aes256Gcm::new_from_slice(key)
+            .map(EncryptionKey)
+            .map_err(|_| Error::Config("encryption keys must be 32 bytes".into()))
+    }
+
+    /// Creates the key from its unpadded base64url encoding, as used in config.
+    pub fn from_base64(key: &str) -> Result<Self, Error> {
+        let key = URL_SAFE_NO_PAD
+            .decode(key)
+            .map_err(|_| Error::Config("encryption keys must be base64url encoded".into()))?;
+        EncryptionKey::new(&key)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Header {
+    pub alg: String,
+    pub enc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+}
+
+/// Whether `token` has the five parts of a compact JWE rather than the three
+/// of a JWS.
+pub(crate) fn is_encrypted(token: &str) -> bool {
+    token.matches('.').count() == 4
+}
+
+pub(crate) fn encode(
+    kid: &str,
+    claims: &impl Serialize,
+    key: &EncryptionKey,
+) -> Result<String, Error> {
+    let header = Header {
+        alg: ALG.to_string(),
+        enc: ENC.to_string(),
+        kid: Some(kid.to_string()),
+        typ: Some("JWT".to_string()),
+    };
+    let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    // 编码后的 header 作为附加认证数据, 防止被篡改
+    let mut ciphertext = key
+        .0
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &serde_json::to_vec(claims)?,
+                aad: header.as_bytes(),
+            },
+        )
+        .expect("AES-GCM encrypts payloads of any practical size");
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_SIZE);
+
+    // dir 模式下 encrypted key 部分为空
+    Ok(format!(
+        "{}..{}.{}.{}",
+        header,
+        URL_SAFE_NO_PAD.encode(nonce),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag)
+    ))
+}
+
+/// A token split into its parts, not yet decrypted.
+pub(crate) struct Encrypted<'a> {
+    pub header: Header,
+    aad: &'a str,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+pub(crate) fn decode(token: &str) -> Result<Encrypted<'_>, Error> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [aad, encrypted_key, nonce, ciphertext, tag] = parts[..] else {
+        return Err(Error::Malformed);
+    };
+    if !encrypted_key.is_empty() {
+        return Err(Error::Malformed);
+    }
+
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| Error::Malformed);
+    let header: Header = serde_json::from_slice(&decode(aad)?)?;
+    if header.alg != ALG || header.enc != ENC {
+        return Err(Error::Malformed);
+    }
+    let nonce = decode(nonce)?;
+    if nonce.len() != NONCE_SIZE {
+        return Err(Error::Malformed);
+    }
+    let mut ciphertext = decode(ciphertext)?;
+    ciphertext.extend(decode(tag)?);
+    Ok(Encrypted {
+        header,
+        aad,
+        nonce,
+        ciphertext,
+    })
+}
+
+impl<'a> Encrypted<'a> {
+    pub fn decrypt<C: DeserializeOwned>(&self, key: &EncryptionKey) -> Result<C, Error> {
+        let claims = key
+            .0
+            .decrypt(
+                Nonce::<Aes256Gcm>::from_slice(&self.nonce),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: self.aad.as_bytes(),
+                },
+            )
+            .map_err(|_| Error::Decryption)?;
+        Ok(serde_json::from_slice(&claims)?)
+    }
+}
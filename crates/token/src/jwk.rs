@@ -0,0 +1,218 @@
+//! JSON Web Keys (RFC 7517), used to publish the public verification keys so
+//! other services can check our tokens without sharing a secret.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::algorithm::{
+    Algorithm, EcdsaVerifyingKey, Ed25519VerifyingKey, RsaVerifyingKey, Verifier,
+};
+use crate::Error;
+
+/// A single public key. Only the members needed for RSA, P-256 and Ed25519
+/// signature keys are modelled; unknown members are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    // 保持为字符串, 其他服务的 JWKS 中可能含有我们不支持的算法
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+impl Jwk {
+    fn new(kty: &str, algorithm: Algorithm) -> Self {
+        Jwk {
+            kty: kty.to_string(),
+            kid: None,
+            alg: Some(algorithm.as_str().to_string()),
+            usage: Some("sig".to_string()),
+            crv: None,
+            n: None,
+            e: None,
+            x: None,
+            y: None,
+        }
+    }
+
+    pub(crate) fn rsa(n: &[u8], e: &[u8]) -> Self {
+        Jwk {
+            n: Some(URL_SAFE_NO_PAD.encode(n)),
+            e: Some(URL_SAFE_NO_PAD.encode(e)),
+            ..Jwk::new("RSA", Algorithm::Rs256)
+        }
+    }
+
+    pub(crate) fn p256(x: &[u8], y: &[u8]) -> Self {
+        Jwk {
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(x)),
+            y: Some(URL_SAFE_NO_PAD.encode(y)),
+            ..Jwk::new("EC", Algorithm::Es256)
+        }
+    }
+
+    pub(crate) fn ed25519(x: &[u8]) -> Self {
+        Jwk {
+            crv: Some("Ed25519".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(x)),
+            ..Jwk::new("OKP", Algorithm::EdDsa)
+        }
+    }
+
+    /// The signature algorithm of this key, taken from `alg` or derived from
+    /// `kty` and `crv`. `None` for keys rc-token cannot verify with, such as
+    /// encryption keys or other curves.
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        if self.usage.as_deref().is_some_and(|usage| usage != "sig") {
+            return None;
+        }
+        let algorithm = match &self.alg {
+            Some(alg) => alg.parse().ok()?,
+            None => match (self.kty.as_str(), self.crv.as_deref()) {
+                ("RSA", _) => Algorithm::Rs256,
+                ("EC", Some("P-256")) => Algorithm::Es256,
+                ("OKP", Some("Ed25519")) => Algorithm::EdDsa,
+                _ => return None,
+            },
+        };
+        // 对称密钥不应出现在公开的 JWKS 中
+        algorithm.is_asymmetric().then_some(algorithm)
+    }
+
+    /// Builds a verifier from the public key, or `None` if the key is not
+    /// usable for token signatures (see `algorithm`).
+    pub fn verifier(&self) -> Result<Option<Arc<dyn Verifier>>, Error> {
+        let Some(algorithm) = self.algorithm() else {
+            return Ok(None);
+        };
+        let verifier: Arc<dyn Verifier> = match (algorithm, self.kty.as_str()) {
+            (Algorithm::Rs256, "RSA") => Arc::new(RsaVerifyingKey::from_components(
+                &self.member("n", &self.n)?,
+                &self.member("e", &self.e)?,
+            )?),
+            (Algorithm::Es256, "EC") if self.crv.as_deref() == Some("P-256") => {
+                Arc::new(EcdsaVerifyingKey::from_coordinates(
+                    &self.member("x", &self.x)?,
+                    &self.member("y", &self.y)?,
+                )?)
+            }
+            (Algorithm::EdDsa, "OKP") if self.crv.as_deref() == Some("Ed25519") => Arc::new(
+                Ed25519VerifyingKey::from_bytes(&self.member("x", &self.x)?)?,
+            ),
+            _ => {
+                return Err(Error::Config(format!(
+                    "JWK of type `{}` does not match algorithm {}",
+                    self.kty, algorithm
+                )))
+            }
+        };
+        Ok(Some(verifier))
+    }
+
+    /// The JWK thumbprint (RFC 7638): base64url SHA-256 of the required
+    /// members in lexicographic order.
+    pub fn thumbprint(&self) -> Result<String, Error> {
+        let member = |name: &str, value: &Option<String>| {
+            value
+                .clone()
+                .ok_or_else(|| Error::Config(format!("JWK is missing `{}`", name)))
+        };
+        // serde_json 的 Map 按键排序, 序列化结果即为 RFC 7638 要求的格式
+        let required = match self.kty.as_str() {
+            "RSA" => {
+                json!({"e": member("e", &self.e)?, "kty": self.kty, "n": member("n", &self.n)?})
+            }
+            "EC" => json!({
+                "crv": member("crv", &self.crv)?,
+                "kty": self.kty,
+                "x": member("x", &self.x)?,
+                "y": member("y", &self.y)?,
+            }),
+            "OKP" => {
+                json!({"crv": member("crv", &self.crv)?, "kty": self.kty, "x": member("x", &self.x)?})
+            }
+            kty => return Err(Error::Config(format!("unsupported JWK type `{}`", kty))),
+        };
+        Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(required.to_string())))
+    }
+
+    fn member(&self, name: &str, value: &Option<String>) -> Result<Vec<u8>, Error> {
+        let value = value
+            .as_deref()
+            .ok_or_else(|| Error::Config(format!("JWK is missing `{}`", name)))?;
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|_| Error::Config(format!("JWK member `{}` is not base64url", name)))
+    }
+}
+
+/// A JWKS document, `{"keys": [...]}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    pub fn find(&self, kid: &str) -> Option<&Jwk> {
+        self.keys.iter().find(|key| key.kid.as_deref() == Some(kid))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let document = std::fs::read_to_string(path)
+            .map_err(|err| Error::Jwks(format!("unable to read `{}`: {}", path.display(), err)))?;
+        serde_json::from_str(&document)
+            .map_err(|err| Error::Jwks(format!("invalid document `{}`: {}", path.display(), err)))
+    }
+
+    /// Downloads the document from `url`, e.g. another service's
+    /// `/.well-known/jwks.json`.
+    #[cfg(feature = "http")]
+    pub async fn fetch(url: &str) -> Result<Self, Error> {
+        let response = reqwest::get(url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Error::Jwks(format!("unable to fetch `{}`: {}", url, err)))?;
+        let document = response
+            .text()
+            .await
+            .map_err(|err| Error::Jwks(format!("unable to fetch `{}`: {}", url, err)))?;
+        serde_json::from_str(&document)
+            .map_err(|err| Error::Jwks(format!("invalid document `{}`: {}", url, err)))
+    }
+
+    /// Loads the document from an `http(s)://` URL or else from a file path.
+    /// URLs need the `http` feature.
+    pub async fn load(location: &str) -> Result<Self, Error> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            #[cfg(feature = "http")]
+            return JwkSet::fetch(location).await;
+            #[cfg(not(feature = "http"))]
+            return Err(Error::Jwks(format!(
+                "loading `{}` requires the `http` feature",
+                location
+            )));
+        }
+        JwkSet::from_file(location)
+    }
+}
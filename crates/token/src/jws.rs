@@ -0,0 +1,91 @@
+//! Compact JWS serialization (`header.claims.signature`).
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::algorithm::{Algorithm, Signer, Verifier};
+use crate::{Error, Jwk};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Header {
+    pub alg: Algorithm,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    /// Public key embedded in DPoP proofs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwk: Option<Jwk>,
+}
+
+pub(crate) fn encode(
+    kid: &str,
+    claims: &impl Serialize,
+    signer: &dyn Signer,
+) -> Result<String, Error> {
+    let header = Header {
+        alg: signer.algorithm(),
+        kid: Some(kid.to_string()),
+        typ: Some("JWT".to_string()),
+        jwk: None,
+    };
+    encode_with_header(&header, claims, signer)
+}
+
+pub(crate) fn encode_with_header(
+    header: &Header,
+    claims: &impl Serialize,
+    signer: &dyn Signer,
+) -> Result<String, Error> {
+    let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(header)?);
+    let claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signing_input = format!("{}.{}", header, claims);
+    let signature = URL_SAFE_NO_PAD.encode(signer.sign(signing_input.as_bytes())?);
+    Ok(format!("{}.{}", signing_input, signature))
+}
+
+/// A token split into its parts, signature not yet checked.
+pub(crate) struct Unverified<'a> {
+    pub header: Header,
+    signing_input: &'a str,
+    claims: &'a str,
+    signature: Vec<u8>,
+}
+
+pub(crate) fn decode(token: &str) -> Result<Unverified<'_>, Error> {
+    let (signing_input, signature) = token.rsplit_once('.').ok_or(Error::Malformed)?;
+    let (header, claims) = signing_input.split_once('.').ok_or(Error::Malformed)?;
+    if claims.contains('.') {
+        return Err(Error::Malformed);
+    }
+
+    let header = URL_SAFE_NO_PAD
+        .decode(header)
+        .map_err(|_| Error::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| Error::Malformed)?;
+    Ok(Unverified {
+        header: serde_json::from_slice(&header)?,
+        signing_input,
+        claims,
+        signature,
+    })
+}
+
+impl<'a> Unverified<'a> {
+    pub fn verify<C: DeserializeOwned>(&self, verifier: &dyn Verifier) -> Result<C, Error> {
+        // 只接受与 key 对应的算法, 防止 alg 混淆攻击
+        if self.header.alg != verifier.algorithm()
+            || !verifier.verify(self.signing_input.as_bytes(), &self.signature)
+        {
+            return Err(Error::InvalidSignature);
+        }
+
+        let claims = URL_SAFE_NO_PAD
+            .decode(self.claims)
+            .map_err(|_| Error::Malformed)?;
+        Ok(serde_json::from_slice(&claims)?)
+    }
+}
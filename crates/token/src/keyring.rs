@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::algorithm::{Algorithm, HmacKey, LoadedKey, Signer, Verifier};
+use crate::clock::{Clock, SystemClock};
+use crate::{cwt, jwe, jws, EncryptionKey, Error, JwkSet, Purpose, TokenFormat, TokenType};
+
+/// Kid used when the keyring is built from the legacy single `SECRET_KEY`.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// A set of named signing keys.
+///
+/// The active key signs every new token and its id is written to the `kid`
+/// header. Retired keys are kept only to verify tokens issued before a
+/// rotation, so rotating the secret does not log every user out.
+///
+/// With an asymmetric algorithm a keyring may hold public keys only, which is
+/// enough for services that verify tokens but never issue them.
+///
+/// The keyring also carries the clock used for `iat` / `exp` of new tokens
+/// and for the expiry checks when decoding, the system clock by default.
+///
+/// Token types may be issued as encrypted JWE instead of signed JWS, using
+/// the keyring's encryption keys, which rotate the same way as signing keys.
+#[derive(Clone)]
+pub struct KeyRing {
+    active: String,
+    signer: Option<Arc<dyn Signer>>,
+    keys: BTreeMap<String, Arc<dyn Verifier>>,
+    clock: Arc<dyn Clock>,
+    active_encryption: Option<String>,
+    encryption_keys: BTreeMap<String, EncryptionKey>,
+    access_format: TokenFormat,
+    refresh_format: TokenFormat,
+    one_time_format: TokenFormat,
+}
+
+impl KeyRing {
+    fn from_parts(
+        active: String,
+        signer: Option<Arc<dyn Signer>>,
+        keys: BTreeMap<String, Arc<dyn Verifier>>,
+    ) -> Self {
+        KeyRing {
+            active,
+            signer,
+            keys,
+            clock: Arc::new(SystemClock),
+            active_encryption: None,
+            encryption_keys: BTreeMap::new(),
+            access_format: TokenFormat::Signed,
+            refresh_format: TokenFormat::Signed,
+            one_time_format: TokenFormat::Signed,
+        }
+    }
+
+    /// Creates a keyring with a single active HS256 key.
+    pub fn new(kid: &str, secret: &str) -> Self {
+        KeyRing::with_signer(kid, HmacKey::new(secret.as_bytes()))
+    }
+
+    /// Creates a keyring whose active key is `signer`.
+    pub fn with_signer<S: Signer + 'static>(kid: &str, signer: S) -> Self {
+        let signer = Arc::new(signer);
+        let mut keys: BTreeMap<String, Arc<dyn Verifier>> = BTreeMap::new();
+        keys.insert(kid.to_string(), signer.clone());
+        KeyRing::from_parts(kid.to_string(), Some(signer), keys)
+    }
+
+    /// Creates a keyring that can only verify tokens, e.g. from a public key.
+    pub fn verify_only<V: Verifier + 'static>(kid: &str, verifier: V) -> Self {
+        let mut keys: BTreeMap<String, Arc<dyn Verifier>> = BTreeMap::new();
+        keys.insert(kid.to_string(), Arc::new(verifier));
+        KeyRing::from_parts(kid.to_string(), None, keys)
+    }
+
+    /// Adds a retired HS256 key which is still trusted for verification.
+    pub fn with_retired_key(self, kid: &str, secret: &str) -> Self {
+        self.with_verifier(kid, HmacKey::new(secret.as_bytes()))
+    }
+
+    /// Adds a retired key of any algorithm which is still trusted for verification.
+    pub fn with_verifier<V: Verifier + 'static>(mut self, kid: &str, verifier: V) -> Self {
+        self.keys.insert(kid.to_string(), Arc::new(verifier));
+        self
+    }
+
+    /// Replaces the clock, e.g. with a `FixedClock` in tests.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Adds an encryption key and makes it the one used for new encrypted tokens.
+    pub fn with_encryption_key(mut self, kid: &str, key: EncryptionKey) -> Self {
+        self.encryption_keys.insert(kid.to_string(), key);
+        self.active_encryption = Some(kid.to_string());
+        self
+    }
+
+    /// Adds a retired encryption key which is still used for decryption.
+    pub fn with_retired_encryption_key(mut self, kid: &str, key: EncryptionKey) -> Self {
+        self.encryption_keys.insert(kid.to_string(), key);
+        self
+    }
+
+    /// Selects the format new tokens of `token_type` are issued in. The
+    /// format of one-time tokens applies to all purposes.
+    pub fn with_format(mut self, token_type: TokenType, format: TokenFormat) -> Self {
+        match token_type {
+            TokenType::AccessToken => self.access_format = format,
+            TokenType::RefreshToken => self.refresh_format = format,
+            TokenType::OneTime(_) => self.one_time_format = format,
+        }
+        self
+    }
+
+    /// Loads the keyring from the environment (or `.env`).
+    ///
+    /// - `TOKEN_ALGORITHM`: `HS256` (default), `RS256`, `ES256` or `EdDSA`
+    /// - `TOKEN_KEYS`: comma separated `kid:key` pairs, e.g. `k2:new,k1:old`.
+    ///   For HS256 the key is the shared secret, otherwise it is the path of a
+    ///   PKCS#8 private key or SPKI public key PEM file
+    /// - `TOKEN_ACTIVE_KID`: kid of the signing key, defaults to the first pair
+    /// - `TOKEN_ENCRYPTION_KEYS`: comma separated `kid:key` pairs of base64url
+    ///   encoded 32 byte keys, the first one encrypts new tokens
+    /// - `TOKEN_FORMAT` / `REFRESH_TOKEN_FORMAT` / `ONE_TIME_TOKEN_FORMAT`:
+    ///   `jws` (default), `jwe` or `cwt`, the format of new access / refresh /
+    ///   one-time tokens
+    ///
+    /// When `TOKEN_KEYS` is not set, `SECRET_KEY` is used as the only HS256 key.
+    pub fn from_env() -> Result<Self, Error> {
+        let keyring = match dotenvy::var("TOKEN_KEYS") {
+            Ok(keys) => {
+                let algorithm = match dotenvy::var("TOKEN_ALGORITHM") {
+                    Ok(algorithm) => algorithm.parse()?,
+                    Err(_) => Algorithm::Hs256,
+                };
+                let active = dotenvy::var("TOKEN_ACTIVE_KID").ok();
+                KeyRing::from_config(algorithm, &keys, active.as_deref())?
+            }
+            Err(_) => {
+                let secret = dotenvy::var("SECRET_KEY").map_err(|_| {
+                    Error::Config("neither TOKEN_KEYS nor SECRET_KEY is set".into())
+                })?;
+                KeyRing::new(DEFAULT_KEY_ID, &secret)
+            }
+        };
+        keyring.with_encryption_from_env()
+    }
+
+    fn with_encryption_from_env(mut self) -> Result<Self, Error> {
+        if let Ok(keys) = dotenvy::var("TOKEN_ENCRYPTION_KEYS") {
+            for pair in keys
+                .split(',')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+            {
+                let Some((kid, key)) = pair.split_once(':') else {
+                    return Err(Error::Config(format!("expected kid:key, got `{}`", pair)));
+                };
+                let key = EncryptionKey::from_base64(key)?;
+                self = if self.active_encryption.is_none() {
+                    self.with_encryption_key(kid, key)
+                } else {
+                    self.with_retired_encryption_key(kid, key)
+                };
+            }
+        }
+
+        for (name, token_type) in [
+            ("TOKEN_FORMAT", TokenType::AccessToken),
+            ("REFRESH_TOKEN_FORMAT", TokenType::RefreshToken),
+            (
+                "ONE_TIME_TOKEN_FORMAT",
+                TokenType::OneTime(Purpose::EmailVerification),
+            ),
+        ] {
+            if let Ok(format) = dotenvy::var(name) {
+                self = self.with_format(token_type, format.parse()?);
+            }
+        }
+        let encrypts = [
+            self.access_format,
+            self.refresh_format,
+            self.one_time_format,
+        ]
+        .contains(&TokenFormat::Encrypted);
+        if encrypts && self.active_encryption.is_none() {
+            return Err(Error::Config(
+                "`jwe` token format requires TOKEN_ENCRYPTION_KEYS".into(),
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Parses the `kid:key,kid:key` format used by `TOKEN_KEYS`.
+    pub fn from_config(
+        algorithm: Algorithm,
+        keys: &str,
+        active: Option<&str>,
+    ) -> Result<Self, Error> {
+        let mut pairs = Vec::new();
+        for pair in keys
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let Some((kid, key)) = pair.split_once(':') else {
+                return Err(Error::Config(format!("expected kid:key, got `{}`", pair)));
+            };
+            if kid.is_empty() || key.is_empty() {
+                return Err(Error::Config(format!("empty kid or key in `{}`", pair)));
+            }
+            let material = if algorithm.is_asymmetric() {
+                std::fs::read_to_string(key).map_err(|err| {
+                    Error::Config(format!("unable to read key file `{}`: {}", key, err))
+                })?
+            } else {
+                key.to_string()
+            };
+            pairs.push((kid, LoadedKey::load(algorithm, &material)?));
+        }
+
+        let Some((first_kid, _)) = pairs.first() else {
+            return Err(Error::Config("TOKEN_KEYS is empty".into()));
+        };
+        let active = active.unwrap_or(first_kid).to_string();
+        let Some((_, active_key)) = pairs.iter().find(|(kid, _)| *kid == active) else {
+            return Err(Error::Config(format!(
+                "active kid `{}` is not configured",
+                active
+            )));
+        };
+
+        let signer = active_key.signer.clone();
+        let keys = pairs
+            .into_iter()
+            .map(|(kid, key)| (kid.to_string(), key.verifier))
+            .collect();
+        Ok(KeyRing::from_parts(active, signer, keys))
+    }
+
+    /// Builds a verify-only keyring from another issuer's JWKS. Keys which
+    /// are not signature keys of a supported algorithm are skipped.
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self, Error> {
+        let mut keys = BTreeMap::new();
+        let mut first = None;
+        for jwk in &jwks.keys {
+            let Some(verifier) = jwk.verifier()? else {
+                continue;
+            };
+            let kid = jwk
+                .kid
+                .clone()
+                .ok_or_else(|| Error::Config("JWK without `kid`".into()))?;
+            first.get_or_insert_with(|| kid.clone());
+            keys.insert(kid, verifier);
+        }
+        let Some(active) = first else {
+            return Err(Error::Config("JWKS has no usable signature key".into()));
+        };
+        Ok(KeyRing::from_parts(active, None, keys))
+    }
+
+    /// The public keys of the active and retired keys, for publishing as
+    /// `/.well-known/jwks.json`. HS256 secrets are never included.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter_map(|(kid, key)| {
+                    let mut jwk = key.to_jwk()?;
+                    jwk.kid = Some(kid.clone());
+                    Some(jwk)
+                })
+                .collect(),
+        }
+    }
+
+    /// Kid of the key used to sign new tokens.
+    pub fn active_kid(&self) -> &str {
+        &self.active
+    }
+
+    /// Whether the active key is a private key (or shared secret).
+    pub fn can_sign(&self) -> bool {
+        self.signer.is_some()
+    }
+
+    /// Current time according to the keyring's clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// The format new tokens of `token_type` are issued in.
+    pub fn format(&self, token_type: TokenType) -> TokenFormat {
+        match token_type {
+            TokenType::AccessToken => self.access_format,
+            TokenType::RefreshToken => self.refresh_format,
+            TokenType::OneTime(_) => self.one_time_format,
+        }
+    }
+
+    pub(crate) fn encode(
+        &self,
+        token_type: TokenType,
+        claims: impl Serialize,
+    ) -> Result<String, Error> {
+        match self.format(token_type) {
+            TokenFormat::Signed => {
+                let signer = self.signer.as_deref().ok_or(Error::NoSigningKey)?;
+                jws::encode(&self.active, &claims, signer)
+            }
+            TokenFormat::Encrypted => {
+                let kid = self
+                    .active_encryption
+                    .as_deref()
+                    .ok_or(Error::NoEncryptionKey)?;
+                jwe::encode(kid, &claims, &self.encryption_keys[kid])
+            }
+            TokenFormat::Cwt => {
+                let signer = self.signer.as_deref().ok_or(Error::NoSigningKey)?;
+                cwt::encode(&self.active, &claims, signer)
+            }
+            // 需要存储, 只能通过 create_reference_token 签发
+            TokenFormat::Reference => Err(Error::Config(
+                "reference tokens are created with create_reference_token".into(),
+            )),
+        }
+    }
+
+    /// Verifies a signed token or decrypts an encrypted one, whatever format
+    /// is configured for new tokens.
+    pub(crate) fn decode<C: DeserializeOwned>(&self, token: &str) -> Result<C, Error> {
+        if jwe::is_encrypted(token) {
+            self.decrypt(token)
+        } else if cwt::is_cwt(token) {
+            self.verify_cwt(token)
+        } else {
+            self.verify(token)
+        }
+    }
+
+    fn verify_cwt<C: DeserializeOwned>(&self, token: &str) -> Result<C, Error> {
+        let token = cwt::decode(token)?;
+        let kid = token.kid.as_deref().ok_or(Error::MissingClaim("kid"))?;
+        let key = self
+            .keys
+            .get(kid)
+            .ok_or_else(|| Error::UnknownKey(kid.to_string()))?;
+        token.verify(key.as_ref())
+    }
+
+    fn decrypt<C: DeserializeOwned>(&self, token: &str) -> Result<C, Error> {
+        let token = jwe::decode(token)?;
+        let kid = token
+            .header
+            .kid
+            .as_deref()
+            .ok_or(Error::MissingClaim("kid"))?;
+        let key = self
+            .encryption_keys
+            .get(kid)
+            .ok_or_else(|| Error::UnknownKey(kid.to_string()))?;
+        token.decrypt(key)
+    }
+
+    fn verify<C: DeserializeOwned>(&self, token: &str) -> Result<C, Error> {
+        let token = jws::decode(token)?;
+
+        if let Some(kid) = &token.header.kid {
+            let key = self
+                .keys
+                .get(kid)
+                .ok_or_else(|| Error::UnknownKey(kid.clone()))?;
+            return token.verify(key.as_ref());
+        }
+
+        // 没有 kid 的 token 是在引入 keyring 之前签发的, 依次尝试所有受信任的 key
+        let mut last_err = Error::InvalidSignature;
+        for key in self.keys.values() {
+            match token.verify(key.as_ref()) {
+                Ok(claims) => return Ok(claims),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
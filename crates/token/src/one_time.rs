@@ -0,0 +1,137 @@
+//! Single use tokens for links sent by email (verification, password reset,
+//! email change and invites) and for the second step of a login with 2FA.
+//!
+//! Each token is bound to a `Purpose` and to a fingerprint of the user's
+//! credential version at issuance, so it stops working once redeemed or once
+//! the password changes, whichever comes first. The credential version must
+//! change only when the password does (e.g. a counter bumped on every
+//! password change), not when the stored hash is upgraded to new parameters
+//! or a new pepper.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    create_token, decode_token, Error, IssuedToken, KeyRing, RegisteredClaims, ReplayCache,
+    TokenType, Validation,
+};
+
+// 指纹只用于比较, 截断后长度固定
+const FINGERPRINT_BYTES: usize = 16;
+
+/// What a one-time token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Purpose {
+    EmailVerification,
+    PasswordReset,
+    EmailChange,
+    Invite,
+    /// Issued after the password step of a login when the user has 2FA
+    /// enabled, exchanged for tokens together with the second factor.
+    MfaChallenge,
+}
+
+impl Purpose {
+    /// Default lifetime of links for this purpose.
+    pub fn default_ttl(self) -> Duration {
+        match self {
+            Purpose::EmailVerification => Duration::days(1),
+            Purpose::PasswordReset | Purpose::EmailChange => Duration::hours(1),
+            Purpose::Invite => Duration::days(7),
+            Purpose::MfaChallenge => Duration::minutes(5),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OneTimeData<T> {
+    #[serde(rename = "d")]
+    data: T,
+    #[serde(rename = "pfp")]
+    fingerprint: String,
+}
+
+/// Fingerprint of a credential version; it changes whenever the password does.
+pub fn credential_fingerprint(credential_version: &str) -> String {
+    URL_SAFE_NO_PAD.encode(&Sha256::digest(credential_version.as_bytes())[..FINGERPRINT_BYTES])
+}
+
+/// Creates a one-time token for `purpose`, bound to the current
+/// `credential_version` of the user it is sent to. For invites, where the
+/// user does not exist yet, pass the inviter's version or an empty string.
+pub fn create_one_time_token(
+    keyring: &KeyRing,
+    purpose: Purpose,
+    data: &impl Serialize,
+    claims: &RegisteredClaims,
+    credential_version: &str,
+    ttl: Duration,
+) -> Result<IssuedToken, Error> {
+    let data = OneTimeData {
+        data,
+        fingerprint: credential_fingerprint(credential_version),
+    };
+    create_token(keyring, &data, claims, TokenType::OneTime(purpose), ttl)
+}
+
+/// A verified one-time token which has not been redeemed yet.
+#[derive(Debug)]
+pub struct OneTimeToken<T> {
+    pub purpose: Purpose,
+    pub data: T,
+    pub registered: RegisteredClaims,
+    fingerprint: String,
+}
+
+/// Verifies a one-time token and checks that it was issued for `purpose`.
+/// Use `sub` or the data to look up the user, then `OneTimeToken::redeem`.
+pub fn decode_one_time_token<T: DeserializeOwned>(
+    keyring: &KeyRing,
+    token: &str,
+    purpose: Purpose,
+    validation: &Validation,
+) -> Result<OneTimeToken<T>, Error> {
+    // 先检查类型, 其他 token 的数据格式不同
+    let claims = decode_token::<serde_json::Value>(keyring, token, validation)?;
+    if claims.token_type != TokenType::OneTime(purpose) {
+        return Err(Error::WrongTokenType);
+    }
+    let data: OneTimeData<T> = serde_json::from_value(claims.data)?;
+    Ok(OneTimeToken {
+        purpose,
+        data: data.data,
+        registered: claims.registered,
+        fingerprint: data.fingerprint,
+    })
+}
+
+impl<T> OneTimeToken<T> {
+    /// Checks the token against the user's current `credential_version` and
+    /// marks it as used in `used`, which must be shared by all instances.
+    pub async fn redeem(
+        self,
+        credential_version: &str,
+        used: &dyn ReplayCache,
+    ) -> Result<T, Error> {
+        if self.fingerprint != credential_fingerprint(credential_version) {
+            return Err(Error::PasswordChanged);
+        }
+        let jti = self
+            .registered
+            .jti
+            .as_deref()
+            .ok_or(Error::MissingClaim("jti"))?;
+        let expires_at = self
+            .registered
+            .exp
+            .and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+            .ok_or(Error::MissingClaim("exp"))?;
+        if !used.insert(jti, expires_at).await? {
+            return Err(Error::AlreadyUsed);
+        }
+        Ok(self.data)
+    }
+}
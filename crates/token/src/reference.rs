@@ -0,0 +1,198 @@
+//! Opaque reference tokens: random strings carrying no data, resolved through
+//! a store which keeps the token contents under the SHA-256 of the token.
+//! Unlike JWTs they can be dropped from the store at any time and reveal
+//! nothing to clients, at the cost of a lookup per request.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::clock::{Clock, SystemClock};
+use crate::{Claims, Error, IssuedToken, KeyRing, RegisteredClaims, TokenType, Validation};
+
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "mysql")]
+pub use self::mysql::MySqlReferenceTokenStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisReferenceTokenStore;
+
+/// Prefix of reference tokens, so they can be told apart from JWTs.
+pub const REFERENCE_TOKEN_PREFIX: &str = "rt_";
+
+const TOKEN_BYTES: usize = 32;
+
+/// The contents of a reference token as kept by the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredToken {
+    pub token_type: TokenType,
+    /// The token data as JSON.
+    pub data: String,
+    /// Includes `sub`, `exp` and `jti`, the same claims a JWT would carry.
+    pub claims: RegisteredClaims,
+}
+
+impl StoredToken {
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.claims
+            .exp
+            .and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+    }
+}
+
+/// Storage of reference tokens, keyed by `reference_token_hash` so that a
+/// leaked store does not contain usable tokens.
+#[async_trait]
+pub trait ReferenceTokenStore: Send + Sync {
+    async fn insert(&self, hash: &str, token: &StoredToken) -> Result<(), Error>;
+
+    /// The stored token, `None` if unknown or removed. Expired entries may
+    /// still be returned, expiry is checked by `resolve_reference_token`.
+    async fn get(&self, hash: &str) -> Result<Option<StoredToken>, Error>;
+
+    async fn remove(&self, hash: &str) -> Result<(), Error>;
+}
+
+/// Process local store, for tests and single instance deployments.
+pub struct MemoryReferenceTokenStore {
+    tokens: Mutex<HashMap<String, StoredToken>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryReferenceTokenStore {
+    fn default() -> Self {
+        MemoryReferenceTokenStore {
+            tokens: Mutex::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl MemoryReferenceTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the clock expired entries are pruned by, e.g. with the
+    /// `FixedClock` of the keyring in tests.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+#[async_trait]
+impl ReferenceTokenStore for MemoryReferenceTokenStore {
+    async fn insert(&self, hash: &str, token: &StoredToken) -> Result<(), Error> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = self.clock.now();
+        tokens.retain(|_, token| token.expires_at().is_some_and(|exp| exp > now));
+        tokens.insert(hash.to_string(), token.clone());
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<StoredToken>, Error> {
+        Ok(self.tokens.lock().unwrap().get(hash).cloned())
+    }
+
+    async fn remove(&self, hash: &str) -> Result<(), Error> {
+        self.tokens.lock().unwrap().remove(hash);
+        Ok(())
+    }
+}
+
+/// Whether `token` is a reference token rather than a JWS or JWE.
+pub fn is_reference_token(token: &str) -> bool {
+    token.starts_with(REFERENCE_TOKEN_PREFIX)
+}
+
+/// The key a reference token is stored under.
+pub fn reference_token_hash(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a random reference token valid for `ttl` and stores its contents.
+/// The generated claims are the same as those of `create_token`.
+pub async fn create_reference_token(
+    keyring: &KeyRing,
+    store: &dyn ReferenceTokenStore,
+    data: &impl Serialize,
+    claims: &RegisteredClaims,
+    token_type: TokenType,
+    ttl: Duration,
+) -> Result<IssuedToken, Error> {
+    let now = keyring.now();
+    let mut random = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut random);
+    let token = format!(
+        "{}{}",
+        REFERENCE_TOKEN_PREFIX,
+        URL_SAFE_NO_PAD.encode(random)
+    );
+
+    let registered = RegisteredClaims {
+        iat: Some(now.timestamp()),
+        exp: Some((now + ttl).timestamp()),
+        jti: Some(textnonce::TextNonce::sized(16).unwrap().to_string()),
+        ..claims.clone()
+    };
+    let stored = StoredToken {
+        token_type,
+        data: serde_json::to_string(data)?,
+        claims: registered.clone(),
+    };
+    store.insert(&reference_token_hash(&token), &stored).await?;
+
+    Ok(IssuedToken {
+        token,
+        claims: registered,
+    })
+}
+
+/// Looks up a reference token and checks its claims against `validation`,
+/// like `decode_token` does for JWTs.
+pub async fn resolve_reference_token<T: DeserializeOwned>(
+    keyring: &KeyRing,
+    store: &dyn ReferenceTokenStore,
+    token: &str,
+    validation: &Validation,
+) -> Result<Claims<T>, Error> {
+    if !is_reference_token(token) {
+        return Err(Error::Malformed);
+    }
+    // 未知的 token 与伪造的 JWT 同样处理
+    let stored = store
+        .get(&reference_token_hash(token))
+        .await?
+        .ok_or(Error::InvalidSignature)?;
+    validation.validate(&stored.claims, false, keyring.now().timestamp())?;
+
+    Ok(Claims {
+        token_type: stored.token_type,
+        data: serde_json::from_str(&stored.data).map_err(corrupt_entry)?,
+        registered: stored.claims,
+    })
+}
+
+// 存储中的内容由服务端写入, 无法解析是存储的问题, 不是客户端的 token 格式错误
+fn corrupt_entry(err: serde_json::Error) -> Error {
+    Error::Store(format!("invalid stored reference token: {}", err))
+}
+
+/// Removes a reference token from the store; it is rejected from then on.
+pub async fn remove_reference_token(
+    store: &dyn ReferenceTokenStore,
+    token: &str,
+) -> Result<(), Error> {
+    store.remove(&reference_token_hash(token)).await
+}
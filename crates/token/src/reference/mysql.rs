@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{MySql, Pool};
+
+use super::{ReferenceTokenStore, StoredToken};
+use crate::Error;
+
+/// Stores reference tokens in the `reference_tokens` table. The subject and
+/// expiry get their own columns so tokens can be purged per user or by age.
+pub struct MySqlReferenceTokenStore {
+    pool: Pool<MySql>,
+}
+
+impl MySqlReferenceTokenStore {
+    pub fn new(pool: Pool<MySql>) -> Self {
+        MySqlReferenceTokenStore { pool }
+    }
+
+    /// Removes tokens which have expired.
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM reference_tokens WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(result.rows_affected())
+    }
+
+    /// Removes all tokens issued to `subject`, e.g. when the password changes.
+    pub async fn remove_subject(&self, subject: &str) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM reference_tokens WHERE subject = ?")
+            .bind(subject)
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl ReferenceTokenStore for MySqlReferenceTokenStore {
+    async fn insert(&self, hash: &str, token: &StoredToken) -> Result<(), Error> {
+        let expires_at = token.expires_at().ok_or(Error::MissingClaim("exp"))?;
+        sqlx::query(
+            "INSERT INTO reference_tokens (token_hash, subject, token, expires_at) \
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(hash)
+        .bind(&token.claims.sub)
+        .bind(serde_json::to_string(token).map_err(super::corrupt_entry)?)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<StoredToken>, Error> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT token FROM reference_tokens WHERE token_hash = ?")
+                .bind(hash)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(store_error)?;
+        match row {
+            Some((token,)) => Ok(Some(
+                serde_json::from_str(&token).map_err(super::corrupt_entry)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn remove(&self, hash: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM reference_tokens WHERE token_hash = ?")
+            .bind(hash)
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(())
+    }
+}
+
+fn store_error(err: sqlx::Error) -> Error {
+    Error::Store(err.to_string())
+}
@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+
+use super::{ReferenceTokenStore, StoredToken};
+use crate::Error;
+
+const KEY_PREFIX: &str = "aii_server:token:reference:";
+
+/// Stores reference tokens as JSON under redis keys which expire together
+/// with the token. All requests share one connection, see
+/// `RedisRevocationStore`.
+pub struct RedisReferenceTokenStore {
+    connection: ConnectionManager,
+}
+
+impl RedisReferenceTokenStore {
+    pub fn new(connection: ConnectionManager) -> Self {
+        RedisReferenceTokenStore { connection }
+    }
+
+    /// Connects to the redis server at `url`, e.g. `redis://127.0.0.1/`.
+    pub async fn open(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url).map_err(store_error)?;
+        Ok(RedisReferenceTokenStore::new(
+            ConnectionManager::new(client).await.map_err(store_error)?,
+        ))
+    }
+}
+
+#[async_trait]
+impl ReferenceTokenStore for RedisReferenceTokenStore {
+    async fn insert(&self, hash: &str, token: &StoredToken) -> Result<(), Error> {
+        let expires_at = token.expires_at().ok_or(Error::MissingClaim("exp"))?;
+        let ttl = (expires_at - Utc::now()).num_seconds();
+        if ttl <= 0 {
+            return Ok(());
+        }
+        let mut conn = self.connection.clone();
+        conn.set_ex::<_, _, ()>(
+            format!("{}{}", KEY_PREFIX, hash),
+            serde_json::to_string(token).map_err(super::corrupt_entry)?,
+            ttl as usize,
+        )
+        .await
+        .map_err(store_error)
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<StoredToken>, Error> {
+        let mut conn = self.connection.clone();
+        let token: Option<String> = conn
+            .get(format!("{}{}", KEY_PREFIX, hash))
+            .await
+            .map_err(store_error)?;
+        match token {
+            Some(token) => Ok(Some(
+                serde_json::from_str(&token).map_err(super::corrupt_entry)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn remove(&self, hash: &str) -> Result<(), Error> {
+        let mut conn = self.connection.clone();
+        conn.del::<_, ()>(format!("{}{}", KEY_PREFIX, hash))
+            .await
+            .map_err(store_error)
+    }
+}
+
+fn store_error(err: redis::RedisError) -> Error {
+    Error::Store(err.to_string())
+}
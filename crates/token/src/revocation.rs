@@ -0,0 +1,110 @@
+//! Denylist of revoked tokens, keyed by the token nonce (`jti`, or `n` for
+//! legacy tokens).
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::de::DeserializeOwned;
+
+use crate::clock::{Clock, SystemClock};
+use crate::{decode_token, Claims, Error, KeyRing, RegisteredClaims, Validation};
+
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "mysql")]
+pub use self::mysql::MySqlRevocationStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisRevocationStore;
+
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    /// Denylists `nonce`. Entries only need to be kept until `expires_at`,
+    /// after that the token is rejected because it has expired.
+    async fn revoke(&self, nonce: &str, expires_at: DateTime<Utc>) -> Result<(), Error>;
+
+    async fn is_revoked(&self, nonce: &str) -> Result<bool, Error>;
+}
+
+/// Process local store, for tests and single instance deployments.
+pub struct MemoryRevocationStore {
+    revoked: Mutex<HashMap<String, DateTime<Utc>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryRevocationStore {
+    fn default() -> Self {
+        MemoryRevocationStore {
+            revoked: Mutex::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the clock entries expire by, e.g. with the `FixedClock` of
+    /// the keyring in tests.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn revoke(&self, nonce: &str, expires_at: DateTime<Utc>) -> Result<(), Error> {
+        let mut revoked = self.revoked.lock().unwrap();
+        let now = self.clock.now();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        revoked.insert(nonce.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn is_revoked(&self, nonce: &str) -> Result<bool, Error> {
+        let revoked = self.revoked.lock().unwrap();
+        Ok(revoked
+            .get(nonce)
+            .is_some_and(|expires_at| *expires_at > self.clock.now()))
+    }
+}
+
+/// `decode_token` followed by a denylist lookup of the token nonce.
+pub async fn decode_unrevoked_token<T: DeserializeOwned>(
+    keyring: &KeyRing,
+    token: &str,
+    validation: &Validation,
+    store: &dyn RevocationStore,
+) -> Result<Claims<T>, Error> {
+    let claims = decode_token(keyring, token, validation)?;
+    let nonce = claims
+        .registered
+        .jti
+        .as_deref()
+        .ok_or(Error::MissingClaim("jti"))?;
+    if store.is_revoked(nonce).await? {
+        return Err(Error::Revoked);
+    }
+    Ok(claims)
+}
+
+/// Denylists a decoded token until it expires.
+pub async fn revoke_token(
+    claims: &RegisteredClaims,
+    store: &dyn RevocationStore,
+) -> Result<(), Error> {
+    let nonce = claims.jti.as_deref().ok_or(Error::MissingClaim("jti"))?;
+    let exp = claims.exp.ok_or(Error::MissingClaim("exp"))?;
+    let expires_at = Utc
+        .timestamp_opt(exp, 0)
+        .single()
+        .ok_or(Error::MissingClaim("exp"))?;
+    store.revoke(nonce, expires_at).await
+}
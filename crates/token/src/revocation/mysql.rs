@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{MySql, Pool};
+
+use super::RevocationStore;
+use crate::Error;
+
+/// Stores revoked nonces in the `revoked_tokens` table.
+pub struct MySqlRevocationStore {
+    pool: Pool<MySql>,
+}
+
+impl MySqlRevocationStore {
+    pub fn new(pool: Pool<MySql>) -> Self {
+        MySqlRevocationStore { pool }
+    }
+
+    /// Removes entries of tokens which have expired anyway.
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await
+            .map_err(store_error)?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MySqlRevocationStore {
+    async fn revoke(&self, nonce: &str, expires_at: DateTime<Utc>) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO revoked_tokens (nonce, expires_at) VALUES (?, ?) \
+             ON DUPLICATE KEY UPDATE expires_at = VALUES(expires_at)",
+        )
+        .bind(nonce)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+        Ok(())
+    }
+
+    async fn is_revoked(&self, nonce: &str) -> Result<bool, Error> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT nonce FROM revoked_tokens WHERE nonce = ? AND expires_at > ?")
+                .bind(nonce)
+                .bind(Utc::now())
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(store_error)?;
+        Ok(row.is_some())
+    }
+}
+
+fn store_error(err: sqlx::Error) -> Error {
+    Error::Store(err.to_string())
+}
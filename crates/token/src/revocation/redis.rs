@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+
+use super::RevocationStore;
+use crate::Error;
+
+const KEY_PREFIX: &str = "aii_server:token:revoked:";
+
+/// Stores revoked nonces as redis keys which expire together with the token.
+///
+/// All requests share one multiplexed connection, which reconnects by itself
+/// after the server went away.
+pub struct RedisRevocationStore {
+    connection: ConnectionManager,
+}
+
+impl RedisRevocationStore {
+    pub fn new(connection: ConnectionManager) -> Self {
+        RedisRevocationStore { connection }
+    }
+
+    /// Connects to the redis server at `url`, e.g. `redis://127.0.0.1/`.
+    pub async fn open(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url).map_err(store_error)?;
+        Ok(RedisRevocationStore::new(
+            ConnectionManager::new(client).await.map_err(store_error)?,
+        ))
+    }
+}
+
+#[async_trait]
+impl RevocationStore for RedisRevocationStore {
+    async fn revoke(&self, nonce: &str, expires_at: DateTime<Utc>) -> Result<(), Error> {
+        let ttl = (expires_at - Utc::now()).num_seconds();
+        if ttl <= 0 {
+            return Ok(());
+        }
+        let mut conn = self.connection.clone();
+        conn.set_ex::<_, _, ()>(format!("{}{}", KEY_PREFIX, nonce), 1, ttl as usize)
+            .await
+            .map_err(store_error)
+    }
+
+    async fn is_revoked(&self, nonce: &str) -> Result<bool, Error> {
+        let mut conn = self.connection.clone();
+        conn.exists(format!("{}{}", KEY_PREFIX, nonce))
+            .await
+            .map_err(store_error)
+    }
+}
+
+fn store_error(err: redis::RedisError) -> Error {
+    Error::Store(err.to_string())
+}
@@ -0,0 +1,210 @@
+//! Encryption of personal data such as emails and phone numbers in database
+//! columns.
+//!
+//! Values are encrypted with AES-256-GCM under a versioned key and stored
+//! as `version:base64url(nonce || ciphertext || tag)`, so keys can be
+//! rotated while old values are still readable. The column name is bound as
+//! associated data, so a value copied into another column does not decrypt.
+//!
+//! Encrypted values cannot be searched, so exact-match lookups go through a
+//! blind index next to the ciphertext: an HMAC-SHA256 of the column name and
+//! the normalized value under a separate key. The index key cannot be
+//! rotated without recomputing every index.
+
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FieldError {
+    #[error("invalid field encryption config: {0}")]
+    Config(String),
+    #[error("field encryption is not configured")]
+    NotConfigured,
+    #[error("unknown field encryption key `{0}`")]
+    UnknownKey(String),
+    #[error("malformed encrypted field")]
+    Malformed,
+    #[error("encrypted field does not decrypt, wrong key or column")]
+    Decrypt,
+}
+
+/// Field encryption keys by version and the blind index key.
+///
+/// The default has no keys, `is_enabled` is false and callers keep such
+/// values in plain text.
+#[derive(Clone, Default)]
+pub struct FieldCipher {
+    current: Option<String>,
+    keys: BTreeMap<String, Aes256Gcm>,
+    index_key: Vec<u8>,
+}
+
+// 不输出密钥
+impl std::fmt::Debug for FieldCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FieldCipher")
+            .field("current", &self.current)
+            .field("versions", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl FieldCipher {
+    /// Encrypts new values with the 32 byte `key` named `version`, and
+    /// computes blind indexes with `index_key` of at least 32 bytes.
+    pub fn new(version: &str, key: &[u8], index_key: &[u8]) -> Result<Self, FieldError> {
+        if index_key.len() < KEY_SIZE {
+            return Err(FieldError::Config(format!(
+                "blind index key must be at least {} bytes",
+                KEY_SIZE
+            )));
+        }
+        let mut cipher = FieldCipher {
+            index_key: index_key.to_vec(),
+            ..Default::default()
+        }
+        .with_retired(version, key)?;
+        cipher.current = Some(version.to_string());
+        Ok(cipher)
+    }
+
+    /// Adds a retired key which is still used to decrypt old values.
+    pub fn with_retired(mut self, version: &str, key: &[u8]) -> Result<Self, FieldError> {
+        if version.is_empty() || version.contains([':', ',']) {
+            return Err(FieldError::Config(format!(
+                "invalid key version `{}`",
+                version
+            )));
+        }
+        let key = Aes256Gcm::new_from_slice(key).map_err(|_| {
+            FieldError::Config(format!("key `{}` must be {} bytes", version, KEY_SIZE))
+        })?;
+        self.keys.insert(version.to_string(), key);
+        Ok(self)
+    }
+
+    /// Reads `FIELD_ENCRYPTION_KEYS`, comma separated `version:key` pairs of
+    /// base64url encoded 32 byte keys with the current one first, and the
+    /// base64url encoded `FIELD_BLIND_INDEX_KEY` from the environment (or
+    /// `.env`). Without either, encryption is disabled.
+    pub fn from_env() -> Result<Self, FieldError> {
+        match (
+            dotenvy::var("FIELD_ENCRYPTION_KEYS"),
+            dotenvy::var("FIELD_BLIND_INDEX_KEY"),
+        ) {
+            (Ok(keys), Ok(index_key)) => FieldCipher::parse(&keys, &index_key),
+            (Err(_), Err(_)) => Ok(FieldCipher::default()),
+            _ => Err(FieldError::Config(
+                "FIELD_ENCRYPTION_KEYS and FIELD_BLIND_INDEX_KEY must be set together".into(),
+            )),
+        }
+    }
+
+    /// Parses `version:key` pairs and the index key, all base64url encoded.
+    pub fn parse(keys: &str, index_key: &str) -> Result<Self, FieldError> {
+        let index_key = decode_key(index_key)?;
+        let mut cipher: Option<FieldCipher> = None;
+        for pair in keys
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let Some((version, key)) = pair.split_once(':') else {
+                return Err(FieldError::Config("expected version:key".into()));
+            };
+            let key = decode_key(key)?;
+            cipher = Some(match cipher {
+                None => FieldCipher::new(version, &key, &index_key)?,
+                Some(cipher) => cipher.with_retired(version, &key)?,
+            });
+        }
+        cipher.ok_or_else(|| FieldError::Config("FIELD_ENCRYPTION_KEYS is empty".into()))
+    }
+
+    /// Whether keys are configured.
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Encrypts `value` for `column`, e.g. `users.email`, with the current key.
+    pub fn encrypt(&self, column: &str, value: &str) -> Result<String, FieldError> {
+        let version = self.current.as_deref().ok_or(FieldError::NotConfigured)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[version]
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: column.as_bytes(),
+                },
+            )
+            .expect("AES-GCM encrypts values of any practical size");
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        Ok(format!("{}:{}", version, URL_SAFE_NO_PAD.encode(bytes)))
+    }
+
+    /// Decrypts a value `encrypt` returned for the same `column`, with
+    /// whichever key it was encrypted with.
+    pub fn decrypt(&self, column: &str, encrypted: &str) -> Result<String, FieldError> {
+        let (version, data) = encrypted.split_once(':').ok_or(FieldError::Malformed)?;
+        let key = self
+            .keys
+            .get(version)
+            .ok_or_else(|| FieldError::UnknownKey(version.to_string()))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(data)
+            .map_err(|_| FieldError::Malformed)?;
+        if bytes.len() < NONCE_SIZE {
+            return Err(FieldError::Malformed);
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        let value = key
+            .decrypt(
+                Nonce::<Aes256Gcm>::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: column.as_bytes(),
+                },
+            )
+            .map_err(|_| FieldError::Decrypt)?;
+        String::from_utf8(value).map_err(|_| FieldError::Malformed)
+    }
+
+    /// Whether `encrypted` was made with a key other than the current one
+    /// and should be encrypted again, e.g. the next time it is read.
+    pub fn needs_reencrypt(&self, encrypted: &str) -> bool {
+        encrypted.split_once(':').map(|(version, _)| version) != self.current.as_deref()
+    }
+
+    /// Blind index of `value` in `column`, 43 characters of base64url.
+    /// Normalize the value first (e.g. lowercase emails), the index only
+    /// matches the exact same bytes.
+    pub fn blind_index(&self, column: &str, value: &str) -> Result<String, FieldError> {
+        if !self.is_enabled() {
+            return Err(FieldError::NotConfigured);
+        }
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.index_key)
+            .expect("HMAC accepts any key length");
+        // 列名参与计算, 不同列中相同的值索引不同
+        mac.update(column.as_bytes());
+        mac.update(&[0]);
+        mac.update(value.as_bytes());
+        Ok(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, FieldError> {
+    URL_SAFE_NO_PAD
+        .decode(key.trim())
+        .map_err(|_| FieldError::Config("keys must be base64url encoded".into()))
+}
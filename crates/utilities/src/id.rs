@@ -0,0 +1,203 @@
+//! Time-ordered 64-bit IDs and their short public form.
+//!
+//! `IdGenerator` hands out Snowflake-style IDs: 41 bits of milliseconds
+//! since `EPOCH_MS`, 10 bits of worker id and 12 bits of sequence, with the
+//! sign bit left clear so the IDs also fit a signed `BIGINT`. IDs from one
+//! worker are strictly increasing, IDs from different workers never collide
+//! and all of them sort roughly by creation time.
+//!
+//! `PublicIds` turns an ID into base62 for URLs and tokens, optionally with
+//! a shuffled alphabet so the IDs are not trivially decoded.
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Start of the timestamps, 2026-01-01T00:00:00Z. 41 bits of milliseconds
+/// last until 2095.
+pub const EPOCH_MS: u64 = 1_767_225_600_000;
+
+const WORKER_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+
+/// Largest worker id, workers are numbered 0 to 1023.
+pub const MAX_WORKER_ID: u16 = (1 << WORKER_BITS) - 1;
+
+const MAX_SEQUENCE: u16 = (1 << SEQUENCE_BITS) - 1;
+
+/// The default base62 alphabet.
+pub const BASE62: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdError {
+    #[error("worker id {0} is larger than {MAX_WORKER_ID}")]
+    InvalidWorkerId(u64),
+    #[error("public id alphabet must be 62 distinct ASCII letters and digits")]
+    InvalidAlphabet,
+    #[error("invalid {name}: `{value}`")]
+    Config { name: &'static str, value: String },
+}
+
+/// The parts an ID is made of, see `IdGenerator::parts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdParts {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub worker_id: u16,
+    pub sequence: u16,
+}
+
+/// Generates IDs for one worker. Every process (or thread pool sharing the
+/// generator) running at the same time needs its own worker id.
+#[derive(Debug)]
+pub struct IdGenerator {
+    worker_id: u16,
+    // 上一个 ID 的时间戳 (相对 EPOCH_MS) 和序号
+    last: Mutex<(u64, u16)>,
+}
+
+impl IdGenerator {
+    pub fn new(worker_id: u16) -> Result<Self, IdError> {
+        if worker_id > MAX_WORKER_ID {
+            return Err(IdError::InvalidWorkerId(worker_id.into()));
+        }
+        Ok(IdGenerator {
+            worker_id,
+            last: Mutex::new((0, 0)),
+        })
+    }
+
+    /// Reads the worker id from `ID_WORKER_ID` in the environment (or
+    /// `.env`), 0 if it is not set.
+    pub fn from_env() -> Result<Self, IdError> {
+        let worker_id = match dotenvy::var("ID_WORKER_ID") {
+            Ok(value) => {
+                let worker_id: u64 = value.parse().map_err(|_| IdError::Config {
+                    name: "ID_WORKER_ID",
+                    value,
+                })?;
+                u16::try_from(worker_id)
+                    .ok()
+                    .filter(|id| *id <= MAX_WORKER_ID)
+                    .ok_or(IdError::InvalidWorkerId(worker_id))?
+            }
+            Err(_) => 0,
+        };
+        IdGenerator::new(worker_id)
+    }
+
+    pub fn worker_id(&self) -> u16 {
+        self.worker_id
+    }
+
+    /// A new ID, larger than every ID this generator returned before.
+    ///
+    /// Up to 4096 IDs are available per millisecond; after that, and while
+    /// the system clock is behind the last ID after being set back, this
+    /// waits for the clock to catch up.
+    pub fn next_id(&self) -> u64 {
+        let mut last = self.last.lock().unwrap_or_else(|err| err.into_inner());
+        let (last_ms, last_sequence) = *last;
+        let mut now = elapsed_ms();
+        let sequence = if now <= last_ms && last_sequence < MAX_SEQUENCE {
+            // 同一毫秒内, 或时钟回拨时沿用上一个时间戳
+            now = last_ms;
+            last_sequence + 1
+        } else {
+            while now <= last_ms {
+                std::thread::yield_now();
+                now = elapsed_ms();
+            }
+            0
+        };
+        *last = (now, sequence);
+        (now << (WORKER_BITS + SEQUENCE_BITS))
+            | (u64::from(self.worker_id) << SEQUENCE_BITS)
+            | u64::from(sequence)
+    }
+
+    /// Splits an ID from any worker into its parts.
+    pub fn parts(id: u64) -> IdParts {
+        IdParts {
+            timestamp_ms: (id >> (WORKER_BITS + SEQUENCE_BITS)) + EPOCH_MS,
+            worker_id: ((id >> SEQUENCE_BITS) & u64::from(MAX_WORKER_ID)) as u16,
+            sequence: (id & u64::from(MAX_SEQUENCE)) as u16,
+        }
+    }
+}
+
+// 相对 EPOCH_MS 的毫秒数
+fn elapsed_ms() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    now.saturating_sub(EPOCH_MS)
+}
+
+/// Base62 encoding of IDs for URLs and tokens: at most 11 characters for a
+/// `u64`, 10 to 11 for IDs from `IdGenerator`.
+#[derive(Debug, Clone)]
+pub struct PublicIds {
+    alphabet: [u8; 62],
+}
+
+impl Default for PublicIds {
+    fn default() -> Self {
+        PublicIds::new(BASE62).expect("BASE62 is a valid alphabet")
+    }
+}
+
+impl PublicIds {
+    /// Uses `alphabet`, a permutation of the 62 ASCII letters and digits.
+    /// Keep it fixed once IDs have been published.
+    pub fn new(alphabet: &str) -> Result<Self, IdError> {
+        let alphabet: [u8; 62] = alphabet
+            .as_bytes()
+            .try_into()
+            .map_err(|_| IdError::InvalidAlphabet)?;
+        // BASE62 按 ASCII 排序, 排序后相同即为其排列
+        let mut sorted = alphabet;
+        sorted.sort_unstable();
+        if sorted[..] != *BASE62.as_bytes() {
+            return Err(IdError::InvalidAlphabet);
+        }
+        Ok(PublicIds { alphabet })
+    }
+
+    /// Reads the alphabet from `PUBLIC_ID_ALPHABET` in the environment (or
+    /// `.env`), `BASE62` if it is not set.
+    pub fn from_env() -> Result<Self, IdError> {
+        match dotenvy::var("PUBLIC_ID_ALPHABET") {
+            Ok(alphabet) => PublicIds::new(&alphabet),
+            Err(_) => Ok(PublicIds::default()),
+        }
+    }
+
+    pub fn encode(&self, id: u64) -> String {
+        let mut digits = Vec::with_capacity(11);
+        let mut rest = id;
+        loop {
+            digits.push(self.alphabet[(rest % 62) as usize]);
+            rest /= 62;
+            if rest == 0 {
+                break;
+            }
+        }
+        digits.reverse();
+        String::from_utf8(digits).expect("the alphabet is ASCII")
+    }
+
+    /// The ID `encode` turned into `public_id`. `None` for anything else,
+    /// including the same number with leading zeros, so every ID has
+    /// exactly one public form.
+    pub fn decode(&self, public_id: &str) -> Option<u64> {
+        let bytes = public_id.as_bytes();
+        if bytes.is_empty() || (bytes.len() > 1 && bytes[0] == self.alphabet[0]) {
+            return None;
+        }
+        bytes.iter().try_fold(0u64, |id, byte| {
+            let digit = self.alphabet.iter().position(|c| c == byte)?;
+            id.checked_mul(62)?.checked_add(digit as u64)
+        })
+    }
+}
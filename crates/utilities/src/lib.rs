@@ -1,27 +1,10 @@
-pub mod password {
-    use sha3::{Digest, Sha3_256};
-
-    pub fn check_pw(input_pw: &str, user_salt: &str, user_pw: &str) -> bool {
-        let result = generate_pw(input_pw, user_salt);
-
-        if user_pw == result {
-            return true;
-        }
-
-        false
-    }
-
-    pub fn generate_pw(input_pw: &str, user_salt: &str) -> String {
-        let mut hasher = Sha3_256::new();
-
-        hasher.update(input_pw);
-        hasher.update(user_salt);
-        let result = hasher.finalize();
-        let result = format!("{:02x}", result);
-
-        result
-    }
-}
+pub mod field_encryption;
+pub mod id;
+pub mod password;
+pub mod password_policy;
+pub mod random;
+pub mod strength;
+pub mod totp;
 
 #[cfg(test)]
 mod tests {
@@ -67,4 +50,489 @@ mod tests {
 
         assert_eq!(user_pw, password::generate_pw(input_pw, user_salt));
     }
+
+    const TEST_PARAMS: password::HashParams = password::HashParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_hash_pw() {
+        let user_pw = password::hash_pw("abc", &TEST_PARAMS).unwrap();
+        assert!(user_pw.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(password::check_pw("abc", "", &user_pw));
+        assert!(!password::check_pw("abd", "", &user_pw));
+        assert!(!password::check_pw("abc", "", "$argon2id$broken"));
+
+        // 每次使用不同的盐
+        assert_ne!(user_pw, password::hash_pw("abc", &TEST_PARAMS).unwrap());
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let legacy = "cb705d51c54f75b070004fc8d630612d586b0a468bdbc9fdf47d9993728cdfed";
+        assert!(password::is_legacy(legacy));
+        assert!(password::needs_rehash(legacy, &TEST_PARAMS));
+
+        let user_pw = password::hash_pw("abc", &TEST_PARAMS).unwrap();
+        assert!(!password::needs_rehash(&user_pw, &TEST_PARAMS));
+        // 调整参数后旧的哈希需要重新计算
+        let params = password::HashParams {
+            iterations: 2,
+            ..TEST_PARAMS
+        };
+        assert!(password::needs_rehash(&user_pw, &params));
+    }
+
+    #[test]
+    fn test_pepper() {
+        let old = password::Peppers::new("p1", b"old pepper").unwrap();
+        let user_pw = old.hash_pw("abc", &TEST_PARAMS).unwrap();
+        assert!(user_pw.contains(",keyid="));
+        assert!(old.check_pw("abc", "", &user_pw));
+        assert!(!old.check_pw("abd", "", &user_pw));
+        // 没有 pepper 时无法校验
+        assert!(!password::check_pw("abc", "", &user_pw));
+
+        // 轮换: 旧 pepper 保留用于校验, 登录后按新 pepper 重新计算
+        let peppers = password::Peppers::parse("p2:new pepper,p1:old pepper").unwrap();
+        assert_eq!(peppers.current(), Some("p2"));
+        assert!(peppers.check_pw("abc", "", &user_pw));
+        assert!(peppers.needs_rehash(&user_pw, &TEST_PARAMS));
+        let user_pw = peppers.hash_pw("abc", &TEST_PARAMS).unwrap();
+        assert!(!peppers.needs_rehash(&user_pw, &TEST_PARAMS));
+        assert!(!old.check_pw("abc", "", &user_pw));
+
+        // 未加 pepper 的哈希和旧的 SHA3 哈希仍可校验
+        let plain = password::hash_pw("abc", &TEST_PARAMS).unwrap();
+        assert!(peppers.check_pw("abc", "", &plain));
+        assert!(peppers.needs_rehash(&plain, &TEST_PARAMS));
+        let legacy = "cb705d51c54f75b070004fc8d630612d586b0a468bdbc9fdf47d9993728cdfed";
+        assert!(peppers.check_pw("abc", "salt", legacy));
+
+        assert!(password::Peppers::new("too-long-id", b"x").is_err());
+        assert!(password::Peppers::parse("p1").is_err());
+    }
+
+    #[test]
+    fn test_keyed_digest() {
+        let old = password::Peppers::new("p1", b"old pepper").unwrap();
+        let digest = old.keyed_digest("ABCDE-FGHJK");
+        assert!(digest.starts_with("$hmac-sha256$keyid=p1$"));
+        assert_eq!(digest, old.keyed_digest("ABCDE-FGHJK"));
+        assert!(old.check_keyed_digest("ABCDE-FGHJK", &digest));
+        assert!(!old.check_keyed_digest("ABCDE-FGHJM", &digest));
+
+        // 轮换后旧 pepper 的摘要仍可校验, 删除旧 pepper 后无法校验
+        let peppers = password::Peppers::parse("p2:new pepper,p1:old pepper").unwrap();
+        assert!(peppers.check_keyed_digest("ABCDE-FGHJK", &digest));
+        assert_ne!(peppers.keyed_digest("ABCDE-FGHJK"), digest);
+        let new = password::Peppers::new("p2", b"new pepper").unwrap();
+        assert!(!new.check_keyed_digest("ABCDE-FGHJK", &digest));
+
+        // 没有 pepper 时为 SHA-256, 不能用于校验 pepper 的摘要
+        let plain = password::Peppers::default();
+        let digest = plain.keyed_digest("ABCDE-FGHJK");
+        assert!(digest.starts_with("$sha256$"));
+        assert!(plain.check_keyed_digest("ABCDE-FGHJK", &digest));
+        assert!(!plain.check_keyed_digest("ABCDE-FGHJK", &old.keyed_digest("ABCDE-FGHJK")));
+        assert!(!plain.check_keyed_digest("ABCDE-FGHJK", "$argon2id$v=19$m=19456,t=2,p=1$x$y"));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(password::constant_time_eq(b"secret", b"secret"));
+        assert!(!password::constant_time_eq(b"secret", b"secreT"));
+        assert!(!password::constant_time_eq(b"secret", b"secret2"));
+        assert!(!password::check_pw("abc", "salt", "cb70"));
+    }
+
+    #[test]
+    fn test_random_encodings() {
+        use random::Encoding;
+
+        assert_eq!(Encoding::Hex.encode(&[0, 0xab, 0xff]), "00abff");
+        assert_eq!(Encoding::Base64Url.encode(&[0xfb, 0xff]), "-_8");
+        // Crockford base32 测试向量: "f" -> CR, "fo" -> CSQG
+        assert_eq!(Encoding::Base32.encode(b"f"), "CR");
+        assert_eq!(Encoding::Base32.encode(b"fo"), "CSQG");
+        assert_eq!(Encoding::Base32.encode(b"foobar"), "CSQPYRK1E8");
+        assert_eq!(
+            random::base32_decode("csqpyrk1e8", random::CROCKFORD).unwrap(),
+            b"foobar"
+        );
+        assert!(random::base32_decode("CSQU", random::CROCKFORD).is_none());
+
+        assert_eq!(random::salt().len(), 32);
+        assert_ne!(random::salt(), random::salt());
+        assert_eq!(random::string(16, Encoding::Base32).len(), 26);
+
+        let key = random::api_key("aii");
+        assert!(key.starts_with("aii_"));
+        assert_eq!(key.len(), 4 + 43);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = random::recovery_codes(10);
+        assert_eq!(codes.len(), 10);
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(random::normalize_recovery_code(code).as_ref(), Some(code));
+        }
+
+        assert_eq!(
+            random::normalize_recovery_code(" 7k3qd m9xwo ").as_deref(),
+            Some("7K3QD-M9XW0")
+        );
+        assert_eq!(
+            random::normalize_recovery_code("ilo12-34567").as_deref(),
+            Some("11012-34567")
+        );
+        assert!(random::normalize_recovery_code("7K3QD-M9XW").is_none());
+        assert!(random::normalize_recovery_code("7K3QD-M9XWU").is_none());
+    }
+
+    #[test]
+    fn test_password_strength() {
+        for weak in [
+            "password",
+            "P@ssw0rd",
+            "qwerty123",
+            "aaaaaaaaaa",
+            "abcdefgh",
+            "woaini1314",
+        ] {
+            assert!(strength::estimate(weak, &[]).score <= 1, "{}", weak);
+        }
+        for strong in [
+            "correct horse battery staple",
+            "k8#Vq2!mZr9@Lw",
+            "Tr0ub4dour&3xyz!",
+        ] {
+            assert!(strength::estimate(strong, &[]).score >= 3, "{}", strong);
+        }
+
+        // 包含用户名或邮箱的密码更弱
+        let inputs = ["zhangwei", "zhangwei@example.com"];
+        assert!(
+            strength::estimate("Zhangwei1990", &inputs).guesses_log10
+                < strength::estimate("Zhangwei1990", &[]).guesses_log10
+        );
+        assert_eq!(strength::estimate("", &[]).score, 0);
+    }
+
+    #[test]
+    fn test_password_policy() {
+        use password_policy::{PasswordPolicy, Violation};
+
+        let policy = PasswordPolicy::default();
+        assert!(policy.check("k8#Vq2!mZr9@Lw", &[]).is_ok());
+
+        let violations = policy.check("abc", &[]).unwrap_err();
+        assert!(violations.contains(&Violation::TooShort { min: 8 }));
+        assert!(violations.contains(&Violation::TooFewClasses { min: 2, found: 1 }));
+        assert!(matches!(
+            policy.check("password1", &[]).unwrap_err()[..],
+            [Violation::TooWeak { min: 2, .. }]
+        ));
+        assert_eq!(
+            policy.check(&"aB3!".repeat(40), &[]).unwrap_err(),
+            vec![Violation::TooLong { max: 128 }]
+        );
+
+        let policy = PasswordPolicy {
+            min_classes: 0,
+            min_score: 0,
+            ..Default::default()
+        };
+        assert!(policy.check("aaaaaaaa", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_breached_list() {
+        use password_policy::{BreachedList, PasswordPolicy, Violation};
+
+        // 明文和 HIBP 格式的 SHA-1 混合
+        let list = BreachedList::parse(
+            "# common passwords\n\
+             k8#Vq2!mZr9@Lw\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\n\
+             \n",
+        );
+        assert_eq!(list.len(), 2);
+        assert!(list.contains("password"));
+        assert!(list.contains("k8#Vq2!mZr9@Lw"));
+        assert!(!list.contains("Password"));
+
+        let policy = PasswordPolicy {
+            breached: Some(list),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check("k8#Vq2!mZr9@Lw", &[]).unwrap_err(),
+            vec![Violation::Breached]
+        );
+    }
+
+    #[test]
+    fn test_violation_messages() {
+        use password_policy::{Language, Violation};
+
+        let violation = Violation::TooShort { min: 8 };
+        assert_eq!(violation.code(), "too_short");
+        assert_eq!(violation.message(Language::Zh), "密码至少需要 8 个字符");
+        assert_eq!(
+            violation.message(Language::En),
+            "Password must be at least 8 characters"
+        );
+
+        assert_eq!(
+            Language::from_accept_language("en-US,en;q=0.9,zh-CN;q=0.8"),
+            Language::En
+        );
+        assert_eq!(
+            Language::from_accept_language("fr, zh-Hans;q=0.5"),
+            Language::Zh
+        );
+        assert_eq!(Language::from_accept_language(""), Language::Zh);
+    }
+
+    #[test]
+    fn test_totp_rfc6238() {
+        use totp::Totp;
+
+        // RFC 6238 附录 B 的 SHA1 测试向量
+        let mut totp = Totp::new(b"12345678901234567890".to_vec());
+        totp.digits = 8;
+        for (time, code) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(totp.code(totp.step(time)), code, "{}", time);
+        }
+    }
+
+    #[test]
+    fn test_totp_verify() {
+        use totp::{Totp, DEFAULT_WINDOW};
+
+        let totp = Totp::generate();
+        let now = 1_700_000_000;
+        let step = totp.step(now);
+        let code = totp.code(step);
+        assert_eq!(totp.verify(&code, now, DEFAULT_WINDOW, None), Some(step));
+        assert_eq!(
+            totp.verify(
+                &format!("{} {}", &code[..3], &code[3..]),
+                now,
+                DEFAULT_WINDOW,
+                None
+            ),
+            Some(step)
+        );
+
+        // 时钟误差在窗口内
+        let previous = totp.code(step - 1);
+        assert_eq!(
+            totp.verify(&previous, now, DEFAULT_WINDOW, None),
+            Some(step - 1)
+        );
+        assert_eq!(totp.verify(&previous, now, 0, None), None);
+        assert_eq!(
+            totp.verify(&totp.code(step + 2), now, DEFAULT_WINDOW, None),
+            None
+        );
+
+        // 已使用的时间步及更早的验证码不能再次使用
+        assert_eq!(totp.verify(&code, now, DEFAULT_WINDOW, Some(step)), None);
+        assert_eq!(
+            totp.verify(&previous, now, DEFAULT_WINDOW, Some(step)),
+            None
+        );
+        assert_eq!(
+            totp.verify(&totp.code(step + 1), now, DEFAULT_WINDOW, Some(step)),
+            Some(step + 1)
+        );
+
+        assert_eq!(totp.verify("12345", now, DEFAULT_WINDOW, None), None);
+        assert_eq!(totp.verify("12345a", now, DEFAULT_WINDOW, None), None);
+    }
+
+    #[test]
+    fn test_totp_secret_and_uri() {
+        use totp::Totp;
+
+        // RFC 4648 base32 测试向量
+        let totp = Totp::new(b"foobar".to_vec());
+        assert_eq!(totp.secret_base32(), "MZXW6YTBOI");
+        let parsed = Totp::from_base32("mzxw 6ytb oi======").unwrap();
+        assert_eq!(parsed.secret_base32(), "MZXW6YTBOI");
+        assert!(Totp::from_base32("MZXW1").is_err());
+        assert!(Totp::from_base32("").is_err());
+
+        let totp = Totp::generate();
+        assert_eq!(totp.secret_base32().len(), 32);
+        let restored = Totp::from_base32(&totp.secret_base32()).unwrap();
+        assert_eq!(restored.code(1), totp.code(1));
+
+        assert_eq!(
+            Totp::new(b"foobar".to_vec()).uri("Love & Dream", "bob@example.com"),
+            "otpauth://totp/Love%20%26%20Dream:bob%40example.com?secret=MZXW6YTBOI\
+             &issuer=Love%20%26%20Dream&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_id_generator() {
+        use id::{IdGenerator, EPOCH_MS};
+        use std::collections::HashSet;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let generator = IdGenerator::new(7).unwrap();
+        let ids: Vec<u64> = (0..10_000).map(|_| generator.next_id()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| *id < 1 << 63));
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let parts = IdGenerator::parts(*ids.last().unwrap());
+        assert_eq!(parts.worker_id, 7);
+        assert!(parts.timestamp_ms > EPOCH_MS && parts.timestamp_ms <= now);
+        assert!(now - parts.timestamp_ms < 60_000);
+
+        // 不同 worker 的 ID 不会重复
+        let other = IdGenerator::new(8).unwrap();
+        let mut seen: HashSet<u64> = ids.into_iter().collect();
+        assert!((0..10_000).all(|_| seen.insert(other.next_id())));
+
+        assert!(IdGenerator::new(1024).is_err());
+    }
+
+    #[test]
+    fn test_public_ids() {
+        use id::{IdGenerator, PublicIds};
+
+        let public_ids = PublicIds::default();
+        assert_eq!(public_ids.encode(0), "0");
+        assert_eq!(public_ids.encode(61), "z");
+        assert_eq!(public_ids.encode(62), "10");
+        assert_eq!(public_ids.encode(u64::MAX), "LygHa16AHYF");
+        assert_eq!(public_ids.decode("LygHa16AHYF"), Some(u64::MAX));
+        assert_eq!(public_ids.decode("LygHa16AHYG"), None);
+        assert_eq!(public_ids.decode("010"), None);
+        assert_eq!(public_ids.decode("1-0"), None);
+        assert_eq!(public_ids.decode(""), None);
+
+        let id = IdGenerator::new(1).unwrap().next_id();
+        assert_eq!(public_ids.decode(&public_ids.encode(id)), Some(id));
+
+        // 打乱的字母表
+        let shuffled =
+            PublicIds::new("kT3xQ9mZaLw7RbN2cYp5VdH8eJf1GgU4hKi6MjSnPoqrstuvyzABCDEFIWXlO0")
+                .unwrap();
+        assert_ne!(shuffled.encode(id), public_ids.encode(id));
+        assert_eq!(shuffled.decode(&shuffled.encode(id)), Some(id));
+        assert!(PublicIds::new(&"a".repeat(62)).is_err());
+        assert!(PublicIds::new(&id::BASE62[1..]).is_err());
+    }
+
+    #[test]
+    fn test_field_encryption() {
+        use field_encryption::{FieldCipher, FieldError};
+
+        let index_key = [7u8; 32];
+        let old = FieldCipher::new("v1", &[1u8; 32], &index_key).unwrap();
+        let encrypted = old.encrypt("users.email", "bob@example.com").unwrap();
+        assert!(encrypted.starts_with("v1:"));
+        assert_eq!(
+            old.decrypt("users.email", &encrypted).unwrap(),
+            "bob@example.com"
+        );
+        // 每次使用不同的 nonce
+        assert_ne!(
+            encrypted,
+            old.encrypt("users.email", "bob@example.com").unwrap()
+        );
+        // 复制到其他列的密文无法解密
+        assert!(matches!(
+            old.decrypt("users.phone", &encrypted),
+            Err(FieldError::Decrypt)
+        ));
+        // 修改密文中的一个字符
+        let flipped = if encrypted.as_bytes()[20] == b'A' {
+            "B"
+        } else {
+            "A"
+        };
+        let tampered = format!("{}{}{}", &encrypted[..20], flipped, &encrypted[21..]);
+        assert!(old.decrypt("users.email", &tampered).is_err());
+
+        // 轮换: 旧密钥保留用于解密
+        let rotated = FieldCipher::new("v2", &[2u8; 32], &index_key)
+            .unwrap()
+            .with_retired("v1", &[1u8; 32])
+            .unwrap();
+        assert!(rotated.needs_reencrypt(&encrypted));
+        assert_eq!(
+            rotated.decrypt("users.email", &encrypted).unwrap(),
+            "bob@example.com"
+        );
+        let reencrypted = rotated.encrypt("users.email", "bob@example.com").unwrap();
+        assert!(!rotated.needs_reencrypt(&reencrypted));
+        assert!(matches!(
+            old.decrypt("users.email", &reencrypted),
+            Err(FieldError::UnknownKey(_))
+        ));
+
+        // blind index 与密钥版本无关, 不同列不同
+        let index = old.blind_index("users.email", "bob@example.com").unwrap();
+        assert_eq!(index.len(), 43);
+        assert_eq!(
+            rotated
+                .blind_index("users.email", "bob@example.com")
+                .unwrap(),
+            index
+        );
+        assert_ne!(
+            old.blind_index("users.phone", "bob@example.com").unwrap(),
+            index
+        );
+        assert_ne!(
+            old.blind_index("users.email", "Bob@example.com").unwrap(),
+            index
+        );
+
+        let disabled = FieldCipher::default();
+        assert!(!disabled.is_enabled());
+        assert!(disabled.encrypt("users.email", "bob@example.com").is_err());
+        assert!(disabled
+            .blind_index("users.email", "bob@example.com")
+            .is_err());
+    }
+
+    #[test]
+    fn test_field_encryption_config() {
+        use field_encryption::FieldCipher;
+
+        // 32 字节的 base64url
+        let key = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE";
+        let cipher = FieldCipher::parse(&format!("v2:{},v1:{}", key, key), key).unwrap();
+        assert!(cipher
+            .encrypt("users.email", "x")
+            .unwrap()
+            .starts_with("v2:"));
+
+        assert!(FieldCipher::parse("v1:AQEB", key).is_err());
+        assert!(FieldCipher::parse(&format!("v1:{}", key), "AQEB").is_err());
+        assert!(FieldCipher::parse(key, key).is_err());
+        assert!(FieldCipher::parse("", key).is_err());
+    }
 }
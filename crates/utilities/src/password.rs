@@ -0,0 +1,341 @@
+//! Password hashing.
+//!
+//! New hashes are Argon2id PHC strings (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`)
+//! which carry their own salt and cost. Hashes created before that are a
+//! hex SHA3-256 of password and the user's `salt` column; they are still
+//! accepted by `check_pw` and should be replaced with `hash_pw` after a
+//! successful login, see `needs_rehash`.
+//!
+//! With `Peppers` the password is first run through HMAC-SHA256 with a
+//! secret pepper that is kept out of the database, and the pepper's id is
+//! recorded as the `keyid` parameter of the hash, so peppers can be rotated
+//! the same way as the cost.
+//!
+//! Random secrets such as recovery codes do not need a slow hash; they are
+//! stored as a `Peppers::keyed_digest`, an HMAC-SHA256 with the pepper.
+
+use std::collections::BTreeMap;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordVerifier, Version};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sha3::{Digest, Sha3_256};
+use subtle::ConstantTimeEq;
+
+// keyed_digest 的格式, 与 PHC 字符串类似, 记录所用 pepper 的 id
+const KEYED_DIGEST_PREFIX: &str = "$hmac-sha256$keyid=";
+const DIGEST_PREFIX: &str = "$sha256$";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Hash(#[from] argon2::password_hash::Error),
+    #[error(transparent)]
+    Params(#[from] argon2::Error),
+    #[error("invalid password hashing config: {0}")]
+    Config(String),
+}
+
+/// Argon2id cost parameters, by default the OWASP recommendation of 19 MiB,
+/// 2 iterations and 1 lane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl HashParams {
+    /// Reads `PASSWORD_HASH_MEMORY_KIB`, `PASSWORD_HASH_ITERATIONS` and
+    /// `PASSWORD_HASH_PARALLELISM` from the environment (or `.env`), falling
+    /// back to the defaults for unset values.
+    pub fn from_env() -> Result<Self, Error> {
+        let var = |name: &str, default: u32| match dotenvy::var(name) {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| Error::Config(format!("invalid {}: `{}`", name, value))),
+            Err(_) => Ok(default),
+        };
+        let defaults = HashParams::default();
+        Ok(HashParams {
+            memory_kib: var("PASSWORD_HASH_MEMORY_KIB", defaults.memory_kib)?,
+            iterations: var("PASSWORD_HASH_ITERATIONS", defaults.iterations)?,
+            parallelism: var("PASSWORD_HASH_PARALLELISM", defaults.parallelism)?,
+        })
+    }
+
+    fn argon2(&self, keyid: Option<&str>) -> Result<Argon2<'static>, Error> {
+        let mut builder = ParamsBuilder::new();
+        builder
+            .m_cost(self.memory_kib)
+            .t_cost(self.iterations)
+            .p_cost(self.parallelism);
+        if let Some(keyid) = keyid {
+            builder.keyid(KeyId::new(keyid.as_bytes())?);
+        }
+        Ok(Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            builder.build()?,
+        ))
+    }
+}
+
+/// Secret peppers by id. The current one is used for new hashes, the
+/// others only to check hashes made before a rotation until every user has
+/// logged in again.
+///
+/// The default has no pepper at all, which is what the free functions of
+/// this module use.
+#[derive(Clone, Default)]
+pub struct Peppers {
+    current: Option<String>,
+    keys: BTreeMap<String, Vec<u8>>,
+}
+
+// 不输出 pepper 的内容
+impl std::fmt::Debug for Peppers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Peppers")
+            .field("current", &self.current)
+            .field("ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Peppers {
+    /// Creates peppers whose current pepper is `key`. The id is stored in
+    /// every hash and can be at most 8 bytes.
+    pub fn new(id: &str, key: &[u8]) -> Result<Self, Error> {
+        let mut peppers = Peppers::default().with_retired(id, key)?;
+        peppers.current = Some(id.to_string());
+        Ok(peppers)
+    }
+
+    /// Adds a retired pepper which is still used to check old hashes.
+    pub fn with_retired(mut self, id: &str, key: &[u8]) -> Result<Self, Error> {
+        if id.is_empty() || id.len() > Params::MAX_KEYID_LEN {
+            return Err(Error::Config(format!(
+                "pepper id `{}` must be 1 to {} bytes",
+                id,
+                Params::MAX_KEYID_LEN
+            )));
+        }
+        if key.is_empty() {
+            return Err(Error::Config(format!("pepper `{}` is empty", id)));
+        }
+        self.keys.insert(id.to_string(), key.to_vec());
+        Ok(self)
+    }
+
+    /// Loads `id:pepper` pairs, the first being the current pepper, from
+    /// `PASSWORD_PEPPERS` (comma separated) or from the file named by
+    /// `PASSWORD_PEPPERS_FILE` (one pair per line). Without either, hashes
+    /// are not peppered.
+    pub fn from_env() -> Result<Self, Error> {
+        let pairs = match (
+            dotenvy::var("PASSWORD_PEPPERS"),
+            dotenvy::var("PASSWORD_PEPPERS_FILE"),
+        ) {
+            (Ok(pairs), _) => pairs,
+            (Err(_), Ok(path)) => std::fs::read_to_string(&path).map_err(|err| {
+                Error::Config(format!("unable to read pepper file `{}`: {}", path, err))
+            })?,
+            (Err(_), Err(_)) => return Ok(Peppers::default()),
+        };
+        Peppers::parse(&pairs)
+    }
+
+    /// Parses `id:pepper` pairs separated by commas or newlines.
+    pub fn parse(pairs: &str) -> Result<Self, Error> {
+        let mut peppers = Peppers::default();
+        for pair in pairs
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let Some((id, key)) = pair.split_once(':') else {
+                return Err(Error::Config("expected id:pepper".to_string()));
+            };
+            peppers = peppers.with_retired(id, key.as_bytes())?;
+            peppers.current.get_or_insert_with(|| id.to_string());
+        }
+        Ok(peppers)
+    }
+
+    /// Id of the pepper used for new hashes.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Hashes a password with Argon2id, a random salt and the current pepper.
+    pub fn hash_pw(&self, input_pw: &str, params: &HashParams) -> Result<String, Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        let current = self.current.as_deref();
+        let input = self
+            .pepper(current, input_pw)
+            .expect("current pepper is configured");
+        Ok(params
+            .argon2(current)?
+            .hash_password(&input, &salt)?
+            .to_string())
+    }
+
+    /// Checks a password against an Argon2id hash made with any of the
+    /// peppers, or against a legacy SHA3 hash using `user_salt`.
+    pub fn check_pw(&self, input_pw: &str, user_salt: &str, user_pw: &str) -> bool {
+        if is_legacy(user_pw) {
+            return constant_time_eq(
+                user_pw.as_bytes(),
+                generate_pw(input_pw, user_salt).as_bytes(),
+            );
+        }
+        let Ok(hash) = PasswordHash::new(user_pw) else {
+            return false;
+        };
+        // 使用轮换前的 pepper 且该 pepper 已删除时无法校验
+        let Some(input) = keyid(&hash).and_then(|keyid| self.pepper(keyid.as_deref(), input_pw))
+        else {
+            return false;
+        };
+        // 校验使用哈希中记录的参数, 与当前配置无关
+        Argon2::default().verify_password(&input, &hash).is_ok()
+    }
+
+    /// Whether `user_pw` should be replaced by a new `hash_pw` once the
+    /// password is known: legacy hashes and hashes with a different cost or
+    /// pepper.
+    pub fn needs_rehash(&self, user_pw: &str, params: &HashParams) -> bool {
+        if is_legacy(user_pw) {
+            return true;
+        }
+        let Ok(hash) = PasswordHash::new(user_pw) else {
+            return true;
+        };
+        if hash.algorithm != argon2::ARGON2ID_IDENT || keyid(&hash) != Some(self.current.clone()) {
+            return true;
+        }
+        match Params::try_from(&hash) {
+            Ok(current) => {
+                current.m_cost() != params.memory_kib
+                    || current.t_cost() != params.iterations
+                    || current.p_cost() != params.parallelism
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Digest of a random secret such as a recovery code, as
+    /// `$hmac-sha256$keyid=<id>$<hex>` with the current pepper or
+    /// `$sha256$<hex>` without one. It is fast to compute, unlike `hash_pw`,
+    /// so only use it for secrets with enough entropy of their own.
+    pub fn keyed_digest(&self, input: &str) -> String {
+        self.digest(self.current.as_deref(), input)
+            .expect("current pepper is configured")
+    }
+
+    /// Checks `input` against a `keyed_digest` made with any of the peppers,
+    /// in constant time.
+    pub fn check_keyed_digest(&self, input: &str, digest: &str) -> bool {
+        let id = match digest.strip_prefix(KEYED_DIGEST_PREFIX) {
+            Some(rest) => rest.split_once('$').map(|(id, _)| Some(id)),
+            None => digest.starts_with(DIGEST_PREFIX).then_some(None),
+        };
+        match id.and_then(|id| self.digest(id, input)) {
+            Some(expected) => constant_time_eq(expected.as_bytes(), digest.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// `keyed_digest` with pepper `id`, `None` if the pepper is unknown.
+    fn digest(&self, id: Option<&str>, input: &str) -> Option<String> {
+        let Some(id) = id else {
+            return Some(format!(
+                "{}{:x}",
+                DIGEST_PREFIX,
+                Sha256::digest(input.as_bytes())
+            ));
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.keys.get(id)?).ok()?;
+        mac.update(input.as_bytes());
+        Some(format!(
+            "{}{}${:x}",
+            KEYED_DIGEST_PREFIX,
+            id,
+            mac.finalize().into_bytes()
+        ))
+    }
+
+    /// HMAC of the password with pepper `id`, or the password itself
+    /// without a pepper. `None` if the pepper is unknown.
+    fn pepper(&self, id: Option<&str>, input_pw: &str) -> Option<Vec<u8>> {
+        let Some(id) = id else {
+            return Some(input_pw.as_bytes().to_vec());
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.keys.get(id)?).ok()?;
+        mac.update(input_pw.as_bytes());
+        Some(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+/// The pepper id recorded in a hash: `Some(None)` for unpeppered hashes,
+/// `None` if the hash cannot be read.
+fn keyid(hash: &PasswordHash) -> Option<Option<String>> {
+    let params = Params::try_from(hash).ok()?;
+    if params.keyid().is_empty() {
+        return Some(None);
+    }
+    String::from_utf8(params.keyid().to_vec()).ok().map(Some)
+}
+
+/// Hashes a password with Argon2id and a random salt, without pepper.
+pub fn hash_pw(input_pw: &str, params: &HashParams) -> Result<String, Error> {
+    Peppers::default().hash_pw(input_pw, params)
+}
+
+/// Checks a password against an unpeppered Argon2id hash, or against a
+/// legacy SHA3 hash using `user_salt`.
+pub fn check_pw(input_pw: &str, user_salt: &str, user_pw: &str) -> bool {
+    Peppers::default().check_pw(input_pw, user_salt, user_pw)
+}
+
+/// Compares two secrets in time independent of where they differ, so the
+/// response time does not reveal how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Whether `user_pw` is a legacy SHA3 hash.
+pub fn is_legacy(user_pw: &str) -> bool {
+    !user_pw.starts_with('$')
+}
+
+/// Whether an unpeppered `user_pw` should be replaced by a new `hash_pw`,
+/// see `Peppers::needs_rehash`.
+pub fn needs_rehash(user_pw: &str, params: &HashParams) -> bool {
+    Peppers::default().needs_rehash(user_pw, params)
+}
+
+/// Legacy SHA3-256 of password and salt. Only used to check old hashes,
+/// new passwords are hashed with `hash_pw`.
+pub fn generate_pw(input_pw: &str, user_salt: &str) -> String {
+    let mut hasher = Sha3_256::new();
+
+    hasher.update(input_pw);
+    hasher.update(user_salt);
+    let result = hasher.finalize();
+    let result = format!("{:02x}", result);
+
+    result
+}
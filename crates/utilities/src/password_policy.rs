@@ -0,0 +1,305 @@
+//! Rules new passwords have to follow.
+//!
+//! Violations are returned as `Violation` values with a stable `code()`, so
+//! clients can show their own translation, and a `message()` in Chinese or
+//! English for those that just display it.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use sha1::{Digest, Sha1};
+
+use crate::strength;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("invalid {name}: `{value}`")]
+    InvalidConfig { name: &'static str, value: String },
+    #[error("unable to read breached password list `{path}`: {source}")]
+    BreachedList {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+/// Why a password was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    TooShort {
+        min: usize,
+    },
+    TooLong {
+        max: usize,
+    },
+    /// Fewer than `min` of lowercase, uppercase, digits and symbols.
+    TooFewClasses {
+        min: usize,
+        found: usize,
+    },
+    /// Strength score below `min`, see `strength::estimate`.
+    TooWeak {
+        score: u8,
+        min: u8,
+    },
+    /// Found in the list of breached or common passwords.
+    Breached,
+}
+
+/// Languages `Violation::message` is available in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    Zh,
+    En,
+}
+
+impl Language {
+    /// The first supported language of an `Accept-Language` header, Chinese
+    /// if there is none.
+    pub fn from_accept_language(header: &str) -> Self {
+        header
+            .split(',')
+            .map(|tag| {
+                tag.split(';')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .find_map(|tag| match tag.split('-').next() {
+                Some("zh") => Some(Language::Zh),
+                Some("en") => Some(Language::En),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Violation {
+    /// Stable code for clients, e.g. `too_short`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Violation::TooShort { .. } => "too_short",
+            Violation::TooLong { .. } => "too_long",
+            Violation::TooFewClasses { .. } => "too_few_classes",
+            Violation::TooWeak { .. } => "too_weak",
+            Violation::Breached => "breached",
+        }
+    }
+
+    pub fn message(&self, language: Language) -> String {
+        match (self, language) {
+            (Violation::TooShort { min }, Language::Zh) => format!("密码至少需要 {} 个字符", min),
+            (Violation::TooShort { min }, Language::En) => {
+                format!("Password must be at least {} characters", min)
+            }
+            (Violation::TooLong { max }, Language::Zh) => format!("密码不能超过 {} 个字符", max),
+            (Violation::TooLong { max }, Language::En) => {
+                format!("Password must be at most {} characters", max)
+            }
+            (Violation::TooFewClasses { min, .. }, Language::Zh) => {
+                format!("密码需包含小写字母, 大写字母, 数字, 符号中的至少 {} 种", min)
+            }
+            (Violation::TooFewClasses { min, .. }, Language::En) => format!(
+                "Password must contain at least {} of lowercase letters, uppercase letters, digits and symbols",
+                min
+            ),
+            (Violation::TooWeak { .. }, Language::Zh) => {
+                "密码太容易被猜到, 请避免常见单词, 键盘顺序, 重复字符和个人信息".to_string()
+            }
+            (Violation::TooWeak { .. }, Language::En) => {
+                "Password is too easy to guess, avoid common words, keyboard patterns, repeats and personal information".to_string()
+            }
+            (Violation::Breached, Language::Zh) => {
+                "该密码出现在已泄露的密码列表中, 请换一个".to_string()
+            }
+            (Violation::Breached, Language::En) => {
+                "Password appears in a list of breached passwords, choose another one".to_string()
+            }
+        }
+    }
+}
+
+/// Sorted SHA-1 hashes of breached or common passwords.
+///
+/// The file has one entry per line, either a plain password or an uppercase
+/// or lowercase SHA-1 hex digest, optionally followed by `:count` as in the
+/// Have I Been Pwned downloads. Blank lines and lines starting with `#` are
+/// skipped. Cloning is cheap, the hashes are shared.
+#[derive(Debug, Clone, Default)]
+pub struct BreachedList {
+    hashes: Arc<Vec<[u8; 20]>>,
+}
+
+impl BreachedList {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| PolicyError::BreachedList {
+            path: path.display().to_string(),
+            source,
+        })?;
+        Ok(BreachedList::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut hashes: Vec<[u8; 20]> = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let digest = line.split_once(':').map_or(line, |(digest, _)| digest);
+                parse_sha1(digest).unwrap_or_else(|| sha1(line))
+            })
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        BreachedList {
+            hashes: Arc::new(hashes),
+        }
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.hashes.binary_search(&sha1(password)).is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+}
+
+fn sha1(password: &str) -> [u8; 20] {
+    Sha1::digest(password.as_bytes()).into()
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// Password rules. The default is 8 to 128 characters from at least 2
+/// character classes with a strength score of at least 2, without a
+/// breached password list.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Upper bound, so hashing a password stays cheap.
+    pub max_length: usize,
+    /// Required number of lowercase, uppercase, digit and symbol classes.
+    pub min_classes: usize,
+    /// Required `strength::estimate` score, 0 disables the estimate.
+    pub min_score: u8,
+    pub breached: Option<BreachedList>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            min_classes: 2,
+            min_score: 2,
+            breached: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH`,
+    /// `PASSWORD_MIN_CLASSES`, `PASSWORD_MIN_SCORE` and the path of the list
+    /// in `PASSWORD_BREACHED_LIST` from the environment (or `.env`). The list
+    /// is loaded once here.
+    pub fn from_env() -> Result<Self, PolicyError> {
+        let defaults = PasswordPolicy::default();
+        Ok(PasswordPolicy {
+            min_length: var("PASSWORD_MIN_LENGTH", defaults.min_length)?,
+            max_length: var("PASSWORD_MAX_LENGTH", defaults.max_length)?,
+            min_classes: var("PASSWORD_MIN_CLASSES", defaults.min_classes)?,
+            min_score: var("PASSWORD_MIN_SCORE", defaults.min_score)?,
+            breached: match dotenvy::var("PASSWORD_BREACHED_LIST") {
+                Ok(path) => Some(BreachedList::load(path)?),
+                Err(_) => None,
+            },
+        })
+    }
+
+    /// Checks `password` against every rule. `user_inputs` such as the
+    /// user's name and email make passwords containing them weaker.
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(Violation::TooShort {
+                min: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(Violation::TooLong {
+                max: self.max_length,
+            });
+            // 过长的密码不再估算强度
+            return Err(violations);
+        }
+
+        let found = character_classes(password);
+        if found < self.min_classes {
+            violations.push(Violation::TooFewClasses {
+                min: self.min_classes,
+                found,
+            });
+        }
+        if self.min_score > 0 {
+            let score = strength::estimate(password, user_inputs).score;
+            if score < self.min_score {
+                violations.push(Violation::TooWeak {
+                    score,
+                    min: self.min_score,
+                });
+            }
+        }
+        if self
+            .breached
+            .as_ref()
+            .is_some_and(|list| list.contains(password))
+        {
+            violations.push(Violation::Breached);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn var<T: std::str::FromStr>(name: &'static str, default: T) -> Result<T, PolicyError> {
+    match dotenvy::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| PolicyError::InvalidConfig { name, value }),
+        Err(_) => Ok(default),
+    }
+}
+
+fn character_classes(password: &str) -> usize {
+    let classes: [fn(char) -> bool; 4] = [
+        char::is_lowercase,
+        char::is_uppercase,
+        char::is_numeric,
+        |c| !c.is_alphanumeric(),
+    ];
+    classes
+        .iter()
+        .filter(|class| password.chars().any(class))
+        .count()
+}
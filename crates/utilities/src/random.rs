@@ -0,0 +1,137 @@
+//! Random salts, keys and codes from the operating system's CSPRNG.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand_core::{OsRng, RngCore};
+
+/// Bytes in a salt from `salt`.
+pub const SALT_BYTES: usize = 16;
+
+/// Bytes of randomness in an API key from `api_key`.
+pub const API_KEY_BYTES: usize = 32;
+
+/// Symbols in a recovery code, 5 bits each.
+pub const RECOVERY_CODE_LEN: usize = 10;
+
+/// Crockford base32 alphabet, without the easily confused I, L, O and U.
+pub const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// RFC 4648 base32 alphabet, as used by authenticator apps.
+pub const RFC4648_BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// How random bytes are turned into text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lowercase hex, two characters per byte.
+    Hex,
+    /// Unpadded base64url, safe in URLs and headers.
+    Base64Url,
+    /// Crockford base32 without padding, for codes people read and type.
+    Base32,
+}
+
+impl Encoding {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            Encoding::Base64Url => URL_SAFE_NO_PAD.encode(bytes),
+            Encoding::Base32 => base32_encode(bytes, CROCKFORD),
+        }
+    }
+}
+
+/// `bytes` in unpadded base32 with `alphabet`, e.g. `CROCKFORD` or
+/// `RFC4648_BASE32`.
+pub fn base32_encode(bytes: &[u8], alphabet: &[u8; 32]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u16, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(alphabet[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(alphabet[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+/// The bytes `base32_encode` turned into `text` with `alphabet`. Lowercase
+/// letters are accepted, anything else outside the alphabet is `None`.
+pub fn base32_decode(text: &str, alphabet: &[u8; 32]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in text.bytes() {
+        let value = alphabet.iter().position(|b| *b == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// `len` random bytes.
+pub fn bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// `len` random bytes as text in `encoding`.
+pub fn string(len: usize, encoding: Encoding) -> String {
+    encoding.encode(&bytes(len))
+}
+
+/// A new value for the users' `salt` column, 32 hex characters.
+pub fn salt() -> String {
+    string(SALT_BYTES, Encoding::Hex)
+}
+
+/// An API key such as `aii_3q2-...`, the prefix tells which service it is for
+/// and makes leaked keys easy to find with secret scanners.
+pub fn api_key(prefix: &str) -> String {
+    format!("{}_{}", prefix, string(API_KEY_BYTES, Encoding::Base64Url))
+}
+
+/// A recovery code such as `7K3QD-M9XWA`: 50 bits in Crockford base32.
+pub fn recovery_code() -> String {
+    // 32 整除 256, 取低 5 位不会有偏差
+    let symbols: String = bytes(RECOVERY_CODE_LEN)
+        .iter()
+        .map(|byte| CROCKFORD[*byte as usize & 31] as char)
+        .collect();
+    let (first, second) = symbols.split_at(RECOVERY_CODE_LEN / 2);
+    format!("{}-{}", first, second)
+}
+
+/// `count` recovery codes.
+pub fn recovery_codes(count: usize) -> Vec<String> {
+    (0..count).map(|_| recovery_code()).collect()
+}
+
+/// Brings a recovery code typed by a user into the form `recovery_code`
+/// returns: case, spaces and dashes are ignored and the letters Crockford
+/// base32 reads as digits (`O`, `I`, `L`) are replaced. `None` if it
+/// cannot be a recovery code.
+pub fn normalize_recovery_code(input: &str) -> Option<String> {
+    let symbols: String = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect();
+    if symbols.len() != RECOVERY_CODE_LEN || !symbols.bytes().all(|c| CROCKFORD.contains(&c)) {
+        return None;
+    }
+    let (first, second) = symbols.split_at(RECOVERY_CODE_LEN / 2);
+    Some(format!("{}-{}", first, second))
+}
@@ -0,0 +1,326 @@
+//! Password strength estimate in the spirit of zxcvbn.
+//!
+//! The password is split into the cheapest sequence of patterns an attacker
+//! would try first (common passwords and words, the user's own name or
+//! email, keyboard rows, sequences, repeats and years) with brute force for
+//! the rest. The guesses needed for each part are multiplied, and the total
+//! is mapped to zxcvbn's 0 to 4 score.
+
+/// Result of `estimate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Strength {
+    /// log10 of the estimated number of guesses.
+    pub guesses_log10: f64,
+    /// 0 (too guessable) to 4 (very unguessable), with zxcvbn's thresholds
+    /// of 10^3, 10^6, 10^8 and 10^10 guesses.
+    pub score: u8,
+}
+
+// 常见密码和单词, 按常见程度排序, 排名即所需的猜测次数
+const COMMON: &[&str] = &[
+    "password", "123456", "qwerty", "woaini", "5201314", "1314", "520", "abc123", "iloveyou",
+    "admin", "111111", "welcome", "monkey", "dragon", "letmein", "football", "baseball", "master",
+    "sunshine", "princess", "shadow", "superman", "michael", "trustno1", "passw0rd", "login",
+    "hello", "freedom", "whatever", "qazwsx", "starwars", "secret", "love", "pass", "root", "test",
+    "user", "guest", "china", "beijing", "shanghai", "zhang", "wang", "chen", "yang", "huang",
+    "zhao", "zhou", "xiao", "liu", "lin", "wei", "ming", "hong", "ling", "jing", "xiaoming",
+    "aiai", "baobao", "tiantian", "kuaile", "wodeai", "nihao", "mima", "summer", "winter",
+    "spring", "autumn", "flower", "angel", "baby", "lucky", "happy", "family", "friend",
+    "computer", "internet", "google", "apple", "samsung", "wechat", "tencent", "alibaba", "taobao",
+    "soccer", "hockey", "batman", "killer", "jordan", "charlie", "ginger", "pepper", "cookie",
+    "cheese", "orange", "banana", "purple", "silver", "golden", "tiger", "panda", "cat", "dog",
+    "fish", "bird", "horse", "star", "moon", "sun", "sky", "blue", "red", "black", "white",
+    "green", "access", "server", "system", "database", "company", "office", "manager", "account",
+    "changeme", "default",
+];
+
+// 键盘上相邻的字符
+const KEYBOARD_ROWS: &[&str] = &["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"];
+
+const LEET: &[(char, char)] = &[
+    ('4', 'a'),
+    ('@', 'a'),
+    ('3', 'e'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+    ('+', 't'),
+];
+
+const MIN_MATCH_LEN: usize = 3;
+
+/// A guessable part `start..end` of the password.
+struct Match {
+    start: usize,
+    end: usize,
+    guesses_log10: f64,
+}
+
+/// Estimates how many guesses `password` takes. `user_inputs` are words
+/// an attacker targeting this user would try first, e.g. name and email.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let mut matches = Vec::new();
+    dictionary_matches(&chars, user_inputs, &mut matches);
+    repeat_matches(&chars, &mut matches);
+    sequence_matches(&chars, &mut matches);
+    keyboard_matches(&chars, &mut matches);
+    year_matches(&chars, &mut matches);
+
+    // 动态规划: best[k] 为前 k 个字符所需的最少猜测次数 (log10)
+    let brute_force = cardinality(&chars).log10();
+    let mut best = vec![f64::INFINITY; chars.len() + 1];
+    best[0] = 0.0;
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + brute_force;
+        for m in matches.iter().filter(|m| m.end == end) {
+            best[end] = best[end].min(best[m.start] + m.guesses_log10);
+        }
+    }
+
+    let guesses_log10 = best[chars.len()];
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    Strength {
+        guesses_log10,
+        score,
+    }
+}
+
+/// Size of the character set a brute force attack has to cover.
+fn cardinality(chars: &[char]) -> f64 {
+    let mut size = 0;
+    if chars.iter().any(char::is_ascii_lowercase) {
+        size += 26;
+    }
+    if chars.iter().any(char::is_ascii_uppercase) {
+        size += 26;
+    }
+    if chars.iter().any(char::is_ascii_digit) {
+        size += 10;
+    }
+    if chars.iter().any(char::is_ascii_punctuation) || chars.contains(&' ') {
+        size += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        size += 100;
+    }
+    size.max(10) as f64
+}
+
+fn dictionary_matches(chars: &[char], user_inputs: &[&str], matches: &mut Vec<Match>) {
+    // 用户信息排在常见密码之前, 攻击者最先尝试
+    let mut words: Vec<String> = Vec::new();
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        words.extend(
+            input
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| word.chars().count() >= MIN_MATCH_LEN)
+                .map(str::to_string),
+        );
+        words.push(input);
+    }
+    words.extend(COMMON.iter().map(|word| word.to_string()));
+
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    if lower.len() != chars.len() {
+        return;
+    }
+    let unleeted: Vec<char> = lower
+        .iter()
+        .map(|c| {
+            LEET.iter()
+                .find(|(leet, _)| leet == c)
+                .map_or(*c, |(_, plain)| *plain)
+        })
+        .collect();
+
+    for start in 0..chars.len() {
+        for end in start + MIN_MATCH_LEN..=chars.len() {
+            let candidates = [
+                (lower[start..end].iter().collect::<String>(), 0.0),
+                (
+                    lower[start..end].iter().rev().collect::<String>(),
+                    2f64.log10(),
+                ),
+                (
+                    unleeted[start..end].iter().collect::<String>(),
+                    leet_log10(&lower[start..end]),
+                ),
+            ];
+            for (word, extra) in candidates {
+                if let Some(rank) = words.iter().position(|w| *w == word) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses_log10: ((rank + 1) as f64).log10()
+                            + uppercase_log10(&chars[start..end])
+                            + extra,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// 大小写变化的猜测次数: 全小写不变, 首字母或全部大写 x2, 其他按大写字母个数
+fn uppercase_log10(chars: &[char]) -> f64 {
+    let upper = chars.iter().filter(|c| c.is_uppercase()).count();
+    if upper == 0 {
+        0.0
+    } else if upper == chars.len() || (upper == 1 && chars[0].is_uppercase()) {
+        2f64.log10()
+    } else {
+        upper as f64 * 2f64.log10()
+    }
+}
+
+fn leet_log10(chars: &[char]) -> f64 {
+    let substitutions = chars
+        .iter()
+        .filter(|c| LEET.iter().any(|(leet, _)| leet == *c))
+        .count();
+    substitutions as f64 * 2f64.log10()
+}
+
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start
+            + chars[start..]
+                .iter()
+                .take_while(|c| **c == chars[start])
+                .count();
+        if end - start >= MIN_MATCH_LEN {
+            matches.push(Match {
+                start,
+                end,
+                guesses_log10: (cardinality(&chars[start..start + 1]) * (end - start) as f64)
+                    .log10(),
+            });
+        }
+        start = end;
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let same_class = |a: char, b: char| {
+        (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+            || (a.is_ascii_uppercase() && b.is_ascii_uppercase())
+            || (a.is_ascii_digit() && b.is_ascii_digit())
+    };
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let delta = chars[start + 1] as i32 - chars[start] as i32;
+        let mut end = start + 1;
+        while end < chars.len()
+            && (delta == 1 || delta == -1)
+            && same_class(chars[end - 1], chars[end])
+            && chars[end] as i32 - chars[end - 1] as i32 == delta
+        {
+            end += 1;
+        }
+        if end - start >= MIN_MATCH_LEN {
+            // 从 a, z, 0, 1, 9 开始的序列最先被尝试
+            let first = chars[start];
+            let base: f64 = if "az019AZ".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction: f64 = if delta < 0 { 2.0 } else { 1.0 };
+            matches.push(Match {
+                start,
+                end,
+                guesses_log10: (base * direction * (end - start) as f64).log10(),
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn keyboard_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let lower: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let position = |c: char| {
+        KEYBOARD_ROWS
+            .iter()
+            .enumerate()
+            .find_map(|(row, keys)| keys.chars().position(|k| k == c).map(|col| (row, col)))
+    };
+    let starting_keys: usize = KEYBOARD_ROWS.iter().map(|row| row.len()).sum();
+
+    let mut start = 0;
+    while start < lower.len() {
+        let mut end = start + 1;
+        let mut direction = 0i32;
+        while end < lower.len() {
+            let (Some((row_a, col_a)), Some((row_b, col_b))) =
+                (position(lower[end - 1]), position(lower[end]))
+            else {
+                break;
+            };
+            let step = col_b as i32 - col_a as i32;
+            if row_a != row_b || step.abs() != 1 || (direction != 0 && step != direction) {
+                break;
+            }
+            direction = step;
+            end += 1;
+        }
+        if end - start >= MIN_MATCH_LEN {
+            matches.push(Match {
+                start,
+                end,
+                guesses_log10: (starting_keys as f64 * 2.0 * (end - start) as f64).log10()
+                    + uppercase_log10(&chars[start..end]),
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        for (len, guesses) in [(4, 120.0), (8, 120.0 * 365.0)] {
+            let end = start + len;
+            let Some(digits) = chars.get(start..end) else {
+                continue;
+            };
+            let Ok(number) = digits.iter().collect::<String>().parse::<u32>() else {
+                continue;
+            };
+            let plausible = match len {
+                // 年份
+                4 => (1920..=2039).contains(&number),
+                // yyyymmdd
+                _ => {
+                    let (year, month, day) = (number / 10000, number / 100 % 100, number % 100);
+                    (1920..=2039).contains(&year)
+                        && (1..=12).contains(&month)
+                        && (1..=31).contains(&day)
+                }
+            };
+            if plausible {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses_log10: f64::log10(guesses),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Time-based one-time passwords (RFC 6238) as used by authenticator apps:
+//! HMAC-SHA1 of the number of 30 second steps since the Unix epoch,
+//! truncated to 6 digits.
+//!
+//! Secrets are shown to users as RFC 4648 base32 in an `otpauth://` URI,
+//! usually as a QR code. `Totp::verify` returns the time step a code
+//! belongs to; store it and pass it back next time so a code cannot be used
+//! twice.
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::password::constant_time_eq;
+use crate::random;
+
+/// Bytes in a secret from `Totp::generate`, the 160 bits RFC 4226 recommends.
+pub const SECRET_BYTES: usize = 20;
+
+/// Digits in a code.
+pub const DIGITS: u32 = 6;
+
+/// Seconds in a time step.
+pub const PERIOD: u64 = 30;
+
+/// Steps before and after the current one `verify` accepts by default, for
+/// clocks that are a little off and codes typed near the end of a step.
+pub const DEFAULT_WINDOW: u64 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TotpError {
+    #[error("invalid base32 secret")]
+    InvalidSecret,
+}
+
+/// A TOTP secret with its code length and step.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    pub digits: u32,
+    pub period: u64,
+}
+
+// 不输出密钥
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp")
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+impl Totp {
+    /// Uses `secret` with 6 digit codes and 30 second steps, which is all
+    /// most authenticator apps support.
+    pub fn new(secret: Vec<u8>) -> Self {
+        Totp {
+            secret,
+            digits: DIGITS,
+            period: PERIOD,
+        }
+    }
+
+    /// A new random secret.
+    pub fn generate() -> Self {
+        Totp::new(random::bytes(SECRET_BYTES))
+    }
+
+    /// Reads a secret as returned by `secret_base32`. Case, spaces and
+    /// padding are ignored.
+    pub fn from_base32(secret: &str) -> Result<Self, TotpError> {
+        let secret: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect();
+        match random::base32_decode(&secret, random::RFC4648_BASE32) {
+            Some(bytes) if !bytes.is_empty() => Ok(Totp::new(bytes)),
+            _ => Err(TotpError::InvalidSecret),
+        }
+    }
+
+    /// The secret in unpadded base32, for storing it and for users who
+    /// cannot scan the QR code.
+    pub fn secret_base32(&self) -> String {
+        random::base32_encode(&self.secret, random::RFC4648_BASE32)
+    }
+
+    /// The `otpauth://totp/...` URI authenticator apps read from QR codes.
+    /// `issuer` is the name of the service, `account` tells the user which
+    /// of their accounts it is, e.g. the email address.
+    pub fn uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.secret_base32(),
+            percent_encode(issuer),
+            self.digits,
+            self.period
+        )
+    }
+
+    /// The time step `unix_time` falls into.
+    pub fn step(&self, unix_time: u64) -> u64 {
+        unix_time / self.period
+    }
+
+    /// The code for time step `step` (RFC 4226 HOTP with the step as counter).
+    pub fn code(&self, step: u64) -> String {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        // 动态截断
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            value % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Checks `code` against the steps within `window` of the one
+    /// `unix_time` falls into and returns the matching step.
+    ///
+    /// Steps up to and including `last_step`, the one returned by the last
+    /// successful `verify`, are rejected so an observed code cannot be
+    /// replayed.
+    pub fn verify(
+        &self,
+        code: &str,
+        unix_time: u64,
+        window: u64,
+        last_step: Option<u64>,
+    ) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != self.digits as usize || !code.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let current = self.step(unix_time);
+        (current.saturating_sub(window)..=current.saturating_add(window))
+            .filter(|step| last_step.is_none_or(|last| *step > last))
+            .find(|step| constant_time_eq(self.code(*step).as_bytes(), code.as_bytes()))
+    }
+}
+
+// 只保留 RFC 3986 中的非保留字符
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
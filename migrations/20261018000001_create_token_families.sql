@@ -0,0 +1,18 @@
+-- refresh token 家族: 每次登录一个家族, 检测到 refresh token 重复使用时整个家族作废
+CREATE TABLE IF NOT EXISTS token_families (
+    id VARCHAR(64) NOT NULL PRIMARY KEY,
+    user_id BIGINT UNSIGNED NOT NULL,
+    device VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP NULL,
+    INDEX idx_token_families_user_id (user_id)
+);
+
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    jti VARCHAR(64) NOT NULL PRIMARY KEY,
+    family_id VARCHAR(64) NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_refresh_tokens_family_id (family_id)
+);
@@ -0,0 +1,6 @@
+-- token 黑名单, 以 token 的 nonce (jti) 为键, 过期后可删除
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    nonce VARCHAR(64) NOT NULL PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL,
+    INDEX idx_revoked_tokens_expires_at (expires_at)
+);
@@ -0,0 +1,6 @@
+-- 已使用的 DPoP proof, 以客户端生成的 jti 为键防止重放, 过期后可删除
+CREATE TABLE IF NOT EXISTS dpop_proofs (
+    jti VARCHAR(255) NOT NULL PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL,
+    INDEX idx_dpop_proofs_expires_at (expires_at)
+);
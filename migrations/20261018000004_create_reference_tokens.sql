@@ -0,0 +1,10 @@
+-- reference token, 以 token 的 SHA-256 (base64url) 为键, token 列保存类型, 用户数据 (uid, 设备) 和声明的 JSON
+CREATE TABLE IF NOT EXISTS reference_tokens (
+    token_hash VARCHAR(64) NOT NULL PRIMARY KEY,
+    subject VARCHAR(255) NULL,
+    token TEXT NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_reference_tokens_subject (subject),
+    INDEX idx_reference_tokens_expires_at (expires_at)
+);
@@ -0,0 +1,2 @@
+-- 邮箱验证时间, 通过邮件链接验证后写入
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP NULL;
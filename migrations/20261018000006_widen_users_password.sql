@@ -0,0 +1,3 @@
+-- 密码改为 Argon2id PHC 格式 ($argon2id$v=19$m=...,t=...,p=...$盐$哈希), 比原来的 SHA3 十六进制长
+-- 旧的 SHA3 哈希在用户下次登录时自动更新
+ALTER TABLE users MODIFY COLUMN password VARCHAR(255) NOT NULL;
@@ -0,0 +1,7 @@
+-- 两步验证 (TOTP)
+-- totp_secret: base32 密钥, 登记后确认前 totp_enabled_at 为空
+-- totp_last_step: 最后一次使用的验证码的时间步, 同一验证码不能再次使用
+ALTER TABLE users
+    ADD COLUMN totp_secret VARCHAR(64) NULL,
+    ADD COLUMN totp_enabled_at TIMESTAMP NULL,
+    ADD COLUMN totp_last_step BIGINT UNSIGNED NULL;
@@ -0,0 +1,22 @@
+-- 两步验证的恢复码, 只保存哈希 (与密码相同的 Argon2id), 每个只能使用一次
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT UNSIGNED NOT NULL,
+    code_hash VARCHAR(255) NOT NULL,
+    used_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_recovery_codes_user_id (user_id)
+);
+
+-- 审计日志: 使用恢复码等安全相关的操作
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT UNSIGNED NOT NULL,
+    event VARCHAR(64) NOT NULL,
+    detail VARCHAR(255) NULL,
+    ip VARCHAR(64) NULL,
+    user_agent VARCHAR(255) NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_audit_log_user_id (user_id),
+    INDEX idx_audit_log_event (event)
+);
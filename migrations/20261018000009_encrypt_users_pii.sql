@@ -0,0 +1,11 @@
+-- 邮箱和手机号加密保存
+-- *_encrypted: AES-256-GCM 密文, 格式为 `密钥版本:base64url(nonce || 密文 || tag)`
+-- *_index: 规范化后的值的 HMAC-SHA256 (blind index), 用于精确匹配查询
+-- 配置 FIELD_ENCRYPTION_KEYS 后, 已有的明文邮箱在下次查询到时加密, 明文列置空
+ALTER TABLE users
+    ADD COLUMN email_encrypted TEXT NULL,
+    ADD COLUMN email_index VARCHAR(64) NULL,
+    ADD COLUMN phone_encrypted TEXT NULL,
+    ADD COLUMN phone_index VARCHAR(64) NULL,
+    ADD UNIQUE INDEX users_email_index (email_index),
+    ADD INDEX users_phone_index (phone_index);
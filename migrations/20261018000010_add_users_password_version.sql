@@ -0,0 +1,3 @@
+-- 密码版本, 每次修改密码时加一. 一次性 token 绑定此版本而不是密码哈希,
+-- 登录时按新参数或新 pepper 重新计算哈希不会使已发出的链接失效
+ALTER TABLE users ADD COLUMN password_version INT UNSIGNED NOT NULL DEFAULT 0;
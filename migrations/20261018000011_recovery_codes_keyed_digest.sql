@@ -0,0 +1,3 @@
+-- 恢复码改为保存 HMAC-SHA256 (pepper 为密钥, 见 Peppers::keyed_digest), 校验一次只需计算 HMAC
+-- 之前的 Argon2id 恢复码每次登录最多需要校验 10 次, 不再支持, 这些用户需要重新生成恢复码
+DELETE FROM recovery_codes WHERE code_hash LIKE '$argon2%';
@@ -0,0 +1,3 @@
+-- 手机号明文列, 未配置 FIELD_ENCRYPTION_KEYS 时使用, 与 email 相同
+-- 配置加密后只保存 phone_encrypted 和 phone_index, 已有的明文手机号在下次查询到时加密, 明文列置空
+ALTER TABLE users ADD COLUMN phone VARCHAR(32) NULL;
@@ -0,0 +1,353 @@
+/**
+ * 邮件链接
+ *   邮箱验证, 重置密码, 修改邮箱的链接中带有一次性 token
+ *   token 绑定用途和签发时的密码版本 (users.password_version), 使用一次或修改密码后即失效
+ *   签发: rc_token::create_one_time_token(keyring, purpose, &AccountLink, claims, &user.credential_version(), ttl)
+ */
+use std::sync::Arc;
+
+use crate::api::tags::ApiTags;
+use crate::api::token::ErrorMessage;
+use crate::api::user::{PiiColumns, UserInfo};
+use crate::api::{middlewares, token_family};
+
+use poem::web::Data;
+use poem::{error::InternalServerError, Request, Result};
+use poem_openapi::{payload::Json, ApiResponse, Object, OpenApi};
+use rc_token::{KeyRing, OneTimeToken, Purpose, ReplayCache, Validation};
+use rc_utilities::field_encryption::FieldCipher;
+use rc_utilities::password_policy::{Language, PasswordPolicy};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::MySqlDatabaseError;
+
+/// 一次性 token 中的数据
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountLink {
+    pub uid: u64,
+    /// 待验证的邮箱, 或修改后的新邮箱
+    pub email: Option<String>,
+}
+
+/// Email link request
+#[derive(Debug, Object)]
+struct LinkRequest {
+    /// Token from the link
+    token: String,
+}
+
+/// Password reset request
+#[derive(Debug, Object)]
+struct PasswordResetRequest {
+    /// Token from the link
+    token: String,
+    /// New password
+    password: String,
+}
+
+/// A password policy rule the password breaks
+#[derive(Debug, Object)]
+pub struct PasswordViolation {
+    /// Stable code, e.g. `too_short`, `too_weak`, `breached`
+    pub code: String,
+    /// Message in the language of `Accept-Language`
+    pub message: String,
+}
+
+/// Password rejected by the password policy
+#[derive(Debug, Object)]
+pub struct WeakPasswordMessage {
+    pub code: i32,
+    pub reason: String,
+    pub violations: Vec<PasswordViolation>,
+}
+
+#[derive(ApiResponse)]
+pub enum LinkApiResponse {
+    /// Done
+    #[oai(status = 200)]
+    Ok,
+    /// Invalid, expired or already used link
+    #[oai(status = 400)]
+    InvalidLink(Json<ErrorMessage>),
+    /// The new password does not meet the password policy
+    #[oai(status = 422)]
+    WeakPassword(Json<WeakPasswordMessage>),
+    /// The new email is already used by another account
+    #[oai(status = 409)]
+    EmailTaken(Json<ErrorMessage>),
+}
+
+fn invalid_link(reason: &str) -> Result<LinkApiResponse> {
+    Ok(LinkApiResponse::InvalidLink(Json(ErrorMessage {
+        code: -1,
+        reason: reason.to_string(),
+    })))
+}
+
+fn email_taken() -> LinkApiResponse {
+    LinkApiResponse::EmailTaken(Json(ErrorMessage {
+        code: -1,
+        reason: "该邮箱已被其他账号使用".to_string(),
+    }))
+}
+
+// MySQL ER_DUP_ENTRY, 违反唯一索引
+const DUPLICATE_ENTRY: u16 = 1062;
+
+fn is_duplicate_entry(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => err
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|err| err.number() == DUPLICATE_ENTRY),
+        _ => false,
+    }
+}
+
+/// 按密码策略检查新密码, 不符合时返回各项原因, 语言按 `Accept-Language` 选择
+/// user_inputs 为用户名, 邮箱等不应出现在密码中的内容
+pub fn check_password_policy(
+    policy: &PasswordPolicy,
+    request: &Request,
+    password: &str,
+    user_inputs: &[&str],
+) -> Option<WeakPasswordMessage> {
+    let violations = policy.check(password, user_inputs).err()?;
+    let language = request
+        .headers()
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok())
+        .map(Language::from_accept_language)
+        .unwrap_or_default();
+    let violations: Vec<PasswordViolation> = violations
+        .iter()
+        .map(|violation| PasswordViolation {
+            code: violation.code().to_string(),
+            message: violation.message(language),
+        })
+        .collect();
+    Some(WeakPasswordMessage {
+        code: -1,
+        reason: violations[0].message.clone(),
+        violations,
+    })
+}
+
+pub struct ApiAccount;
+
+#[OpenApi(prefix_path = "/account", tag = "ApiTags::Account")]
+impl ApiAccount {
+    /// 验证邮箱
+    #[oai(path = "/email/verify", method = "post")]
+    async fn verify_email(
+        &self,
+        req: Json<LinkRequest>,
+        used: Data<&Arc<dyn ReplayCache>>,
+        keyring: Data<&Arc<KeyRing>>,
+        validation: Data<&Validation>,
+        cipher: Data<&FieldCipher>,
+    ) -> Result<LinkApiResponse> {
+        let (user, link) = match redeem(
+            &keyring,
+            &validation,
+            &cipher,
+            &req.token,
+            Purpose::EmailVerification,
+            &used,
+        )
+        .await?
+        {
+            Ok(redeemed) => redeemed,
+            Err(reason) => return invalid_link(reason),
+        };
+        // 链接签发后邮箱又被修改过
+        if link.email.is_none() || link.email != user.email {
+            return invalid_link("链接无效或已过期");
+        }
+
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        sqlx::query("UPDATE users SET email_verified_at = NOW() WHERE id = ?")
+            .bind(user.id)
+            .execute(db.get_pool())
+            .await
+            .map_err(InternalServerError)?;
+        Ok(LinkApiResponse::Ok)
+    }
+
+    /// 修改邮箱, 链接发送到新邮箱, 确认后新邮箱即为已验证
+    /// 新邮箱已被其他账号使用时返回 409, 链接同样失效
+    #[oai(path = "/email/change", method = "post")]
+    async fn change_email(
+        &self,
+        req: Json<LinkRequest>,
+        used: Data<&Arc<dyn ReplayCache>>,
+        keyring: Data<&Arc<KeyRing>>,
+        validation: Data<&Validation>,
+        cipher: Data<&FieldCipher>,
+    ) -> Result<LinkApiResponse> {
+        let (user, link) = match redeem(
+            &keyring,
+            &validation,
+            &cipher,
+            &req.token,
+            Purpose::EmailChange,
+            &used,
+        )
+        .await?
+        {
+            Ok(redeemed) => redeemed,
+            Err(reason) => return invalid_link(reason),
+        };
+        let Some(email) = link.email else {
+            return invalid_link("链接无效或已过期");
+        };
+
+        // 配置了 FIELD_ENCRYPTION_KEYS 时只保存密文和 blind index
+        let columns = PiiColumns::email(&cipher, &email).map_err(InternalServerError)?;
+
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        // 新邮箱已被其他账号使用. 同时修改为同一邮箱时由 email_index 的唯一索引拒绝
+        if let Some(other) = UserInfo::find_by_email(db.get_pool(), &cipher, &email).await? {
+            if other.id != user.id {
+                return Ok(email_taken());
+            }
+        }
+        let result = sqlx::query(
+            "UPDATE users SET email = ?, email_encrypted = ?, email_index = ?, \
+             email_verified_at = NOW() WHERE id = ?",
+        )
+        .bind(columns.plain)
+        .bind(columns.encrypted)
+        .bind(columns.index)
+        .bind(user.id)
+        .execute(db.get_pool())
+        .await;
+        match result {
+            Ok(_) => Ok(LinkApiResponse::Ok),
+            Err(e) if is_duplicate_entry(&e) => Ok(email_taken()),
+            Err(e) => Err(InternalServerError(e)),
+        }
+    }
+
+    /// 重置密码, 该用户所有的 refresh token 随即作废
+    #[oai(path = "/password/reset", method = "post")]
+    async fn reset_password(
+        &self,
+        req: Json<PasswordResetRequest>,
+        request: &Request,
+        used: Data<&Arc<dyn ReplayCache>>,
+        keyring: Data<&Arc<KeyRing>>,
+        validation: Data<&Validation>,
+        policy: Data<&PasswordPolicy>,
+    ) -> Result<LinkApiResponse> {
+        let (mut user, token) = match decode_link(
+            &keyring,
+            &validation,
+            middlewares::field_cipher(request),
+            &req.token,
+            Purpose::PasswordReset,
+        )
+        .await?
+        {
+            Ok(decoded) => decoded,
+            Err(reason) => return invalid_link(reason),
+        };
+        // 在使用链接之前检查, 密码不符合要求时链接仍然有效
+        let user_inputs: Vec<&str> = user
+            .email
+            .iter()
+            .map(String::as_str)
+            .chain([user.name.as_str()])
+            .collect();
+        if let Some(message) = check_password_policy(&policy, request, &req.password, &user_inputs)
+        {
+            return Ok(LinkApiResponse::WeakPassword(Json(message)));
+        }
+        if let Err(reason) = use_link(&user, token, &used).await? {
+            return invalid_link(reason);
+        }
+        user.set_password(
+            req.password.clone(),
+            middlewares::peppers(request),
+            middlewares::hash_params(request),
+        )
+        .await?;
+
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        // 密码版本加一, 该用户其他未使用的链接随即失效
+        sqlx::query(
+            "UPDATE users SET password = ?, password_version = password_version + 1 WHERE id = ?",
+        )
+        .bind(&user.password)
+        .bind(user.id)
+        .execute(db.get_pool())
+        .await
+        .map_err(InternalServerError)?;
+        token_family::revoke_user_families(db.get_pool(), user.id)
+            .await
+            .map_err(InternalServerError)?;
+        Ok(LinkApiResponse::Ok)
+    }
+}
+
+/// 校验链接中的 token, 查询对应用户并标记 token 已使用
+/// 链接无效时返回给用户的原因
+async fn redeem(
+    keyring: &KeyRing,
+    validation: &Validation,
+    cipher: &FieldCipher,
+    token: &str,
+    purpose: Purpose,
+    used: &Arc<dyn ReplayCache>,
+) -> Result<Result<(UserInfo, AccountLink), &'static str>> {
+    let (user, token) = match decode_link(keyring, validation, cipher, token, purpose).await? {
+        Ok(decoded) => decoded,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    Ok(use_link(&user, token, used).await?.map(|link| (user, link)))
+}
+
+/// 校验链接中的 token 并查询对应用户, token 尚未标记为已使用
+async fn decode_link(
+    keyring: &KeyRing,
+    validation: &Validation,
+    cipher: &FieldCipher,
+    token: &str,
+    purpose: Purpose,
+) -> Result<Result<(UserInfo, OneTimeToken<AccountLink>), &'static str>> {
+    let Ok(token) =
+        rc_token::decode_one_time_token::<AccountLink>(keyring, token, purpose, validation)
+    else {
+        return Ok(Err("链接无效或已过期"));
+    };
+
+    let db = rc_database::Database::new()
+        .await
+        .expect("Database connection expected");
+    let Some(user) = UserInfo::find_by_id(db.get_pool(), cipher, token.data.uid).await? else {
+        return Ok(Err("链接无效或已过期"));
+    };
+    Ok(Ok((user, token)))
+}
+
+/// 检查密码版本并标记 token 已使用, 返回 token 中的数据
+async fn use_link(
+    user: &UserInfo,
+    token: OneTimeToken<AccountLink>,
+    used: &Arc<dyn ReplayCache>,
+) -> Result<Result<AccountLink, &'static str>> {
+    match token
+        .redeem(&user.credential_version(), used.as_ref())
+        .await
+    {
+        Ok(link) => Ok(Ok(link)),
+        Err(rc_token::Error::AlreadyUsed) => Ok(Err("链接已被使用")),
+        Err(rc_token::Error::PasswordChanged) => Ok(Err("密码已修改, 链接已失效")),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
@@ -0,0 +1,45 @@
+/**
+ * 审计日志
+ *   记录安全相关的操作, 包括操作的用户, 请求的 IP 和 User-Agent
+ */
+use poem::Request;
+use sqlx::{Executor, MySql};
+
+/// 使用恢复码登录, detail 为剩余的恢复码数量
+pub const RECOVERY_CODE_USED: &str = "recovery_code.used";
+/// 生成新的恢复码, 之前的恢复码作废
+pub const RECOVERY_CODES_GENERATED: &str = "recovery_codes.generated";
+
+// 与表中的列宽一致
+const MAX_USER_AGENT_LEN: usize = 255;
+
+/// Records `event` of `user_id`, together with the IP and User-Agent of
+/// `request`. Pass a transaction to record it together with the change.
+pub async fn record<'e>(
+    executor: impl Executor<'e, Database = MySql>,
+    user_id: u64,
+    event: &str,
+    detail: Option<&str>,
+    request: &Request,
+) -> Result<(), sqlx::Error> {
+    let ip = request
+        .remote_addr()
+        .as_socket_addr()
+        .map(|addr| addr.ip().to_string());
+    let user_agent = request
+        .headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+    sqlx::query(
+        "INSERT INTO audit_log (user_id, event, detail, ip, user_agent) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(event)
+    .bind(detail)
+    .bind(ip)
+    .bind(user_agent)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,11 @@
+/**
+ * token 中的用户数据
+ *   服务和 aii-token 共用, aii-token 通过 #[path] 引入此文件, 这里只能依赖 serde
+ */
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct CurrentUser {
+    pub uid: u64,
+    pub device: String,
+}
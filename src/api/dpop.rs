@@ -0,0 +1,92 @@
+/**
+ * DPoP (RFC 9449)
+ *   客户端在请求头 `DPoP` 中附带用自己私钥签名的 proof
+ *   登录时附带 proof, 签发的 token 绑定该公钥 (cnf.jkt)
+ *   之后使用 `Authorization: DPoP <token>` 访问, 每个请求都需要新的 proof
+ */
+use std::sync::Arc;
+
+use poem::Request;
+use rc_token::{
+    DpopProof, DpopValidation, KeyRing, MemoryReplayCache, MySqlReplayCache, RedisReplayCache,
+    ReplayCache,
+};
+
+const DPOP: &str = "DPoP";
+
+/// 按 `TOKEN_DPOP_REPLAY_CACHE` 创建 proof jti 的防重放缓存: memory, mysql 或 redis
+/// 未配置时与 `TOKEN_REVOCATION_STORE` 相同
+pub async fn replay_cache_from_env() -> Result<Arc<dyn ReplayCache>, Box<dyn std::error::Error>> {
+    let kind = dotenvy::var("TOKEN_DPOP_REPLAY_CACHE")
+        .or_else(|_| dotenvy::var("TOKEN_REVOCATION_STORE"))
+        .unwrap_or_else(|_| "memory".to_string());
+    let cache: Arc<dyn ReplayCache> = match kind.as_str() {
+        "memory" => Arc::new(MemoryReplayCache::new()),
+        "mysql" => {
+            let db = rc_database::Database::new().await?;
+            Arc::new(MySqlReplayCache::new(db.get_pool().clone()))
+        }
+        "redis" => {
+            let url =
+                dotenvy::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+            Arc::new(RedisReplayCache::open(&url).await?)
+        }
+        _ => return Err(format!("unsupported TOKEN_DPOP_REPLAY_CACHE `{}`", kind).into()),
+    };
+    Ok(cache)
+}
+
+/// 请求的完整 URL, 与 proof 中的 htu 比较
+/// 服务在反向代理之后时需配置 `TOKEN_DPOP_BASE_URL` 为客户端看到的地址
+pub fn request_url(req: &Request) -> String {
+    let base = dotenvy::var("TOKEN_DPOP_BASE_URL").unwrap_or_else(|_| {
+        let host = req
+            .headers()
+            .get("host")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("localhost");
+        format!("http://{}", host)
+    });
+    format!(
+        "{}{}",
+        base.trim_end_matches('/'),
+        req.original_uri().path()
+    )
+}
+
+/// 校验请求头中的 proof, 没有 proof 时返回 None
+/// access_token 不为空时同时校验 proof 的 ath
+pub async fn check_proof(
+    req: &Request,
+    access_token: Option<&str>,
+    keyring: &KeyRing,
+) -> Result<Option<DpopProof>, rc_token::Error> {
+    let mut proofs = req.headers().get_all(DPOP).iter();
+    let Some(proof) = proofs.next() else {
+        return Ok(None);
+    };
+    // 只允许一个 proof
+    if proofs.next().is_some() {
+        return Err(rc_token::Error::InvalidProof("multiple DPoP headers"));
+    }
+    let proof = proof
+        .to_str()
+        .map_err(|_| rc_token::Error::InvalidProof("malformed proof"))?;
+
+    let validation = DpopValidation::from_env()?;
+    let cache = req
+        .data::<Arc<dyn ReplayCache>>()
+        .cloned()
+        .expect("DPoP replay cache expected");
+    rc_token::verify_dpop_proof(
+        proof,
+        req.method().as_str(),
+        &request_url(req),
+        access_token,
+        keyring.now(),
+        &validation,
+        cache.as_ref(),
+    )
+    .await
+    .map(Some)
+}
@@ -0,0 +1,188 @@
+/**
+ * token introspection (RFC 7662)
+ *   内部服务使用 client credentials (HTTP Basic) 调用, 查询 token 是否有效及其内容
+ *   客户端在 TOKEN_INTROSPECTION_CLIENTS 中配置, 未配置时所有请求都会被拒绝
+ */
+use std::sync::Arc;
+
+use crate::api::tags::ApiTags;
+use crate::api::token::ErrorMessage;
+use crate::api::{reference, token_family};
+
+use poem::error::InternalServerError;
+use poem::web::Data;
+use poem::Result;
+use poem_openapi::auth::Basic;
+use poem_openapi::payload::{Form, Json};
+use poem_openapi::{ApiResponse, Object, OpenApi, SecurityScheme};
+use rc_token::{KeyRing, ReferenceTokenStore, RevocationStore, TokenType, Validation};
+use rc_utilities::password;
+use serde::Deserialize;
+
+/// Client credentials of the calling service
+#[derive(SecurityScheme)]
+#[oai(type = "basic")]
+struct IntrospectionClient(Basic);
+
+/// Introspection request, `application/x-www-form-urlencoded`
+#[derive(Debug, Deserialize, Object)]
+struct IntrospectRequest {
+    /// The token to check
+    token: String,
+    /// `access_token` or `refresh_token`; the type is read from the token itself
+    token_type_hint: Option<String>,
+}
+
+/// Token confirmation (RFC 7800)
+#[derive(Debug, Object)]
+struct IntrospectConfirmation {
+    /// JWK SHA-256 thumbprint
+    jkt: String,
+}
+
+/// Introspection response. Only `active` is set for inactive tokens.
+#[derive(Debug, Default, Object)]
+#[oai(skip_serializing_if_is_none)]
+struct IntrospectResponse {
+    /// Whether the token is valid, unexpired and not revoked
+    active: bool,
+    /// `access_token` or `refresh_token`
+    token_type: Option<String>,
+    /// User id
+    sub: Option<String>,
+    /// User id
+    uid: Option<u64>,
+    /// Device the token was issued to
+    device: Option<String>,
+    /// Space separated scopes
+    scope: Option<String>,
+    /// Confirmation of the DPoP key the token is bound to
+    cnf: Option<IntrospectConfirmation>,
+    /// Expiry, seconds since the unix epoch
+    exp: Option<i64>,
+    /// Issue time, seconds since the unix epoch
+    iat: Option<i64>,
+    /// Not before, seconds since the unix epoch
+    nbf: Option<i64>,
+    /// Issuer
+    iss: Option<String>,
+    /// Audience
+    #[oai(skip_serializing_if_is_empty)]
+    aud: Vec<String>,
+    /// Token id
+    jti: Option<String>,
+}
+
+#[derive(ApiResponse)]
+enum IntrospectApiResponse {
+    /// Introspection result
+    #[oai(status = 200)]
+    Ok(Json<Box<IntrospectResponse>>),
+    /// Unknown client or wrong secret
+    #[oai(status = 401)]
+    Unauthorized(
+        Json<ErrorMessage>,
+        #[oai(header = "WWW-Authenticate")] String,
+    ),
+}
+
+pub struct ApiIntrospection;
+
+#[OpenApi(prefix_path = "/token", tag = "ApiTags::Token")]
+impl ApiIntrospection {
+    /// 查询 token 状态, 供内部服务使用
+    #[oai(path = "/introspect", method = "post")]
+    async fn introspect(
+        &self,
+        client: IntrospectionClient,
+        req: Form<IntrospectRequest>,
+        revocation: Data<&Arc<dyn RevocationStore>>,
+        references: Data<&Arc<dyn ReferenceTokenStore>>,
+        keyring: Data<&Arc<KeyRing>>,
+        validation: Data<&Validation>,
+    ) -> Result<IntrospectApiResponse> {
+        if !authenticate(&client.0) {
+            return Ok(IntrospectApiResponse::Unauthorized(
+                Json(ErrorMessage {
+                    code: -1,
+                    reason: "client 认证失败".to_string(),
+                }),
+                "Basic realm=\"introspection\"".to_string(),
+            ));
+        }
+
+        let claims = match reference::decode_unrevoked_token(
+            &keyring,
+            &req.token,
+            &validation,
+            revocation.as_ref(),
+            references.as_ref(),
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err(err) if !err.is_token_error() => return Err(InternalServerError(err)),
+            Err(_) => return Ok(inactive()),
+        };
+
+        // refresh token 还需要未被使用, 且所属家族未被作废
+        if claims.token_type == TokenType::RefreshToken {
+            let Some(jti) = &claims.registered.jti else {
+                return Ok(inactive());
+            };
+            let db = rc_database::Database::new()
+                .await
+                .expect("Database connection expected");
+            if !token_family::is_active(db.get_pool(), jti)
+                .await
+                .map_err(InternalServerError)?
+            {
+                return Ok(inactive());
+            }
+        }
+
+        let token_type = match claims.token_type {
+            TokenType::AccessToken => "access_token",
+            TokenType::RefreshToken => "refresh_token",
+            // 一次性 token 只用于邮件链接, 不是访问凭证
+            TokenType::OneTime(_) => return Ok(inactive()),
+        };
+        let registered = claims.registered;
+        let response = IntrospectResponse {
+            active: true,
+            token_type: Some(token_type.to_string()),
+            sub: registered.sub.or_else(|| Some(claims.data.uid.to_string())),
+            uid: Some(claims.data.uid),
+            device: Some(claims.data.device),
+            scope: registered.scope.as_ref().map(ToString::to_string),
+            cnf: registered.cnf.as_ref().map(|cnf| IntrospectConfirmation {
+                jkt: cnf.jkt.clone(),
+            }),
+            exp: registered.exp,
+            iat: registered.iat,
+            nbf: registered.nbf,
+            iss: registered.iss,
+            aud: registered.aud,
+            jti: registered.jti,
+        };
+        Ok(IntrospectApiResponse::Ok(Json(Box::new(response))))
+    }
+}
+
+fn inactive() -> IntrospectApiResponse {
+    IntrospectApiResponse::Ok(Json(Box::default()))
+}
+
+/// 按 `TOKEN_INTROSPECTION_CLIENTS` (`client_id:secret,client_id:secret`) 校验调用方
+fn authenticate(client: &Basic) -> bool {
+    let Ok(clients) = dotenvy::var("TOKEN_INTROSPECTION_CLIENTS") else {
+        return false;
+    };
+    clients
+        .split(',')
+        .filter_map(|pair| pair.trim().split_once(':'))
+        .any(|(id, secret)| {
+            id == client.username
+                && password::constant_time_eq(secret.as_bytes(), client.password.as_bytes())
+        })
+}
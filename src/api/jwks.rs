@@ -0,0 +1,29 @@
+/**
+ * jwks
+ *   公开 token 校验公钥 (当前密钥和保留的旧密钥), 其他服务无需共享密钥即可校验 token
+ *   HS256 密钥不会公开, 只使用 HS256 时返回空的 keys
+ */
+use std::sync::Arc;
+
+use poem::error::InternalServerError;
+use poem::http::header::CACHE_CONTROL;
+use poem::web::Data;
+use poem::{handler, Response, Result};
+use rc_token::KeyRing;
+
+/// 未配置 `TOKEN_JWKS_MAX_AGE` 时的缓存时间 (秒)
+const DEFAULT_MAX_AGE: u64 = 300;
+
+#[handler]
+pub fn jwks(keyring: Data<&Arc<KeyRing>>) -> Result<Response> {
+    let body = serde_json::to_string(&keyring.jwks()).map_err(InternalServerError)?;
+    let max_age = dotenvy::var("TOKEN_JWKS_MAX_AGE")
+        .ok()
+        .and_then(|max_age| max_age.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_AGE);
+
+    Ok(Response::builder()
+        .content_type("application/json")
+        .header(CACHE_CONTROL, format!("public, max-age={}", max_age))
+        .body(body))
+}
@@ -0,0 +1,449 @@
+/**
+ * 两步验证 (TOTP)
+ *   登记: 生成密钥, 返回 otpauth:// URI 供认证器 app 扫码, 此时尚未开启
+ *   确认: 提交认证器 app 中的验证码, 正确后开启, 之后登录时需要验证码
+ *   关闭: 需要提交当前的验证码
+ *   开启后, 密码正确时登录接口返回 challenge, 使用 challenge 和验证码再次登录才签发 token
+ *   开启时同时生成一组恢复码, 丢失认证器时可代替验证码, 见 recovery_codes
+ */
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::api::account::AccountLink;
+use crate::api::current_user::CurrentUser;
+use crate::api::scopes::require_session;
+use crate::api::tags::ApiTags;
+use crate::api::token::ErrorMessage;
+use crate::api::user::UserInfo;
+use crate::api::{middlewares, recovery_codes};
+
+use poem::{error::InternalServerError, http::StatusCode, Error, Request, Result};
+use poem_openapi::{payload::Json, ApiResponse, Object, OpenApi};
+use rc_token::{
+    KeyRing, Purpose, RegisteredClaims, ReplayCache, TokenPairConfig, TokenType, Validation,
+};
+use rc_utilities::field_encryption::FieldCipher;
+use rc_utilities::totp::{Totp, DEFAULT_WINDOW};
+use sqlx::types::chrono;
+use sqlx::{MySql, Pool};
+
+/// users 表中的两步验证状态
+#[derive(Debug, sqlx::FromRow)]
+pub struct TotpState {
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub totp_last_step: Option<u64>,
+}
+
+impl TotpState {
+    pub async fn load(pool: &Pool<MySql>, uid: u64) -> sqlx::Result<TotpState> {
+        sqlx::query_as(
+            "SELECT totp_secret, totp_enabled_at, totp_last_step FROM users WHERE id = ?",
+        )
+        .bind(uid)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.totp_enabled_at.is_some() && self.totp_secret.is_some()
+    }
+
+    /// 校验验证码, 成功时记录所用的时间步, 同一验证码不能再次使用
+    /// 保存的密钥无法解码时返回 500, 不当作验证码错误
+    pub async fn verify(&self, pool: &Pool<MySql>, uid: u64, code: &str) -> Result<bool> {
+        let Some(secret) = &self.totp_secret else {
+            return Ok(false);
+        };
+        let totp = Totp::from_base32(secret).map_err(InternalServerError)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let Some(step) = totp.verify(code, now, DEFAULT_WINDOW, self.totp_last_step) else {
+            return Ok(false);
+        };
+        // 同时提交的两个请求只有一个能使用该验证码
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_step = ? \
+             WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+        )
+        .bind(step)
+        .bind(uid)
+        .bind(step)
+        .execute(pool)
+        .await
+        .map_err(InternalServerError)?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+/// 认证器 app 中显示的服务名称
+fn issuer() -> String {
+    dotenvy::var("TOTP_ISSUER").unwrap_or_else(|_| "Love & Dream".to_string())
+}
+
+/// Second factor required to finish the login
+#[derive(Debug, Object)]
+pub struct MfaChallenge {
+    /// Send it back with the second factor, it can be used once
+    pub challenge: String,
+    /// Accepted second factors: `totp`, and `recovery_code` while the user has unused codes
+    pub methods: Vec<String>,
+    /// The challenge expires in seconds
+    pub expires_in: i64,
+}
+
+/// 密码校验通过后签发 challenge, 绑定密码版本, 5 分钟内有效
+pub async fn create_challenge(
+    pool: &Pool<MySql>,
+    keyring: &KeyRing,
+    user: &UserInfo,
+    token_config: &TokenPairConfig,
+) -> Result<MfaChallenge> {
+    let claims = RegisteredClaims {
+        sub: Some(user.id.to_string()),
+        ..RegisteredClaims::from_env()
+    };
+    let ttl = token_config.ttl(TokenType::OneTime(Purpose::MfaChallenge));
+    let challenge = rc_token::create_one_time_token(
+        keyring,
+        Purpose::MfaChallenge,
+        &AccountLink {
+            uid: user.id,
+            email: None,
+        },
+        &claims,
+        &user.credential_version(),
+        ttl,
+    )
+    .map_err(InternalServerError)?;
+    let mut methods = vec!["totp".to_string()];
+    if recovery_codes::remaining(pool, user.id)
+        .await
+        .map_err(InternalServerError)?
+        > 0
+    {
+        methods.push("recovery_code".to_string());
+    }
+    Ok(MfaChallenge {
+        challenge: challenge.token,
+        methods,
+        expires_in: ttl.num_seconds(),
+    })
+}
+
+/// 校验并使用 challenge, 返回对应的用户
+/// 每个 challenge 只能提交一次, 验证码错误时需重新输入密码, 避免穷举验证码
+/// challenge 无效时返回给用户的原因
+pub async fn redeem_challenge(
+    pool: &Pool<MySql>,
+    keyring: &KeyRing,
+    validation: &Validation,
+    challenge: &str,
+    used: &dyn ReplayCache,
+    cipher: &FieldCipher,
+) -> Result<Result<UserInfo, &'static str>> {
+    let Ok(token) = rc_token::decode_one_time_token::<AccountLink>(
+        keyring,
+        challenge,
+        Purpose::MfaChallenge,
+        validation,
+    ) else {
+        return Ok(Err("登录已过期, 请重新登录"));
+    };
+
+    let Some(user) = UserInfo::find_by_id(pool, cipher, token.data.uid).await? else {
+        return Ok(Err("登录已过期, 请重新登录"));
+    };
+
+    match token.redeem(&user.credential_version(), used).await {
+        Ok(_) => Ok(Ok(user)),
+        Err(rc_token::Error::AlreadyUsed) => Ok(Err("验证码已提交过, 请重新登录")),
+        Err(rc_token::Error::PasswordChanged) => Ok(Err("密码已修改, 请重新登录")),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
+
+/// TOTP code request
+#[derive(Debug, Object)]
+struct TotpCodeRequest {
+    /// Code from the authenticator app
+    code: String,
+}
+
+/// TOTP enrollment
+#[derive(Debug, Object)]
+pub struct TotpEnrollment {
+    /// Base32 secret, for users who cannot scan the QR code
+    secret: String,
+    /// `otpauth://` URI to show as a QR code
+    uri: String,
+}
+
+#[derive(ApiResponse)]
+pub enum TotpEnrollApiResponse {
+    /// Secret generated, confirm it with a code to enable 2FA
+    #[oai(status = 200)]
+    Ok(Json<TotpEnrollment>),
+    /// 2FA is already enabled
+    #[oai(status = 409)]
+    AlreadyEnabled(Json<ErrorMessage>),
+}
+
+/// Recovery codes, shown once
+#[derive(Debug, Object)]
+pub struct RecoveryCodes {
+    /// Each code can be used once instead of a TOTP code when logging in
+    recovery_codes: Vec<String>,
+}
+
+#[derive(ApiResponse)]
+pub enum TotpApiResponse {
+    /// Done
+    #[oai(status = 200)]
+    Ok,
+    /// Wrong or already used code
+    #[oai(status = 400)]
+    InvalidCode(Json<ErrorMessage>),
+    /// 2FA is not enrolled, or already enabled or disabled
+    #[oai(status = 409)]
+    Conflict(Json<ErrorMessage>),
+}
+
+#[derive(ApiResponse)]
+pub enum RecoveryCodesApiResponse {
+    /// Done, the previous recovery codes can no longer be used
+    #[oai(status = 200)]
+    Ok(Json<RecoveryCodes>),
+    /// Wrong or already used code
+    #[oai(status = 400)]
+    InvalidCode(Json<ErrorMessage>),
+    /// 2FA is not enrolled, or already enabled or disabled
+    #[oai(status = 409)]
+    Conflict(Json<ErrorMessage>),
+}
+
+fn message(reason: &str) -> Json<ErrorMessage> {
+    Json(ErrorMessage {
+        code: -1,
+        reason: reason.to_string(),
+    })
+}
+
+fn current_uid(request: &Request) -> Result<u64> {
+    request
+        .extensions()
+        .get::<CurrentUser>()
+        .map(|current_user| current_user.uid)
+        .ok_or_else(|| Error::from_status(StatusCode::UNAUTHORIZED))
+}
+
+pub struct ApiMfa;
+
+#[OpenApi(prefix_path = "/mfa", tag = "ApiTags::Mfa")]
+impl ApiMfa {
+    /// 登记 TOTP, 生成新的密钥. 重复登记时之前未确认的密钥失效
+    #[oai(path = "/totp/enroll", method = "post", transform = "require_session")]
+    async fn enroll(&self, request: &Request) -> Result<TotpEnrollApiResponse> {
+        let uid = current_uid(request)?;
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        let user = UserInfo::find_by_id(db.get_pool(), middlewares::field_cipher(request), uid)
+            .await?
+            .ok_or_else(|| Error::from_status(StatusCode::UNAUTHORIZED))?;
+
+        let totp = Totp::generate();
+        let secret = totp.secret_base32();
+        let result = sqlx::query(
+            "UPDATE users SET totp_secret = ?, totp_last_step = NULL \
+             WHERE id = ? AND totp_enabled_at IS NULL",
+        )
+        .bind(&secret)
+        .bind(uid)
+        .execute(db.get_pool())
+        .await
+        .map_err(InternalServerError)?;
+        if result.rows_affected() == 0 {
+            return Ok(TotpEnrollApiResponse::AlreadyEnabled(message(
+                "已开启两步验证",
+            )));
+        }
+
+        let account = user.email.as_deref().unwrap_or(&user.name);
+        Ok(TotpEnrollApiResponse::Ok(Json(TotpEnrollment {
+            uri: totp.uri(&issuer(), account),
+            secret,
+        })))
+    }
+
+    /// 提交认证器 app 中的验证码, 确认后开启两步验证, 返回恢复码
+    #[oai(path = "/totp/confirm", method = "post", transform = "require_session")]
+    async fn confirm(
+        &self,
+        req: Json<TotpCodeRequest>,
+        request: &Request,
+    ) -> Result<RecoveryCodesApiResponse> {
+        let uid = current_uid(request)?;
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        let state = TotpState::load(db.get_pool(), uid)
+            .await
+            .map_err(InternalServerError)?;
+        if state.totp_secret.is_none() {
+            return Ok(RecoveryCodesApiResponse::Conflict(message(
+                "请先登记两步验证",
+            )));
+        }
+        if state.is_enabled() {
+            return Ok(RecoveryCodesApiResponse::Conflict(message(
+                "已开启两步验证",
+            )));
+        }
+        if !state.verify(db.get_pool(), uid, &req.code).await? {
+            return Ok(RecoveryCodesApiResponse::InvalidCode(message(
+                "验证码不正确",
+            )));
+        }
+
+        // 同时提交的确认请求只有一个生成恢复码
+        let result = sqlx::query(
+            "UPDATE users SET totp_enabled_at = NOW() WHERE id = ? AND totp_enabled_at IS NULL",
+        )
+        .bind(uid)
+        .execute(db.get_pool())
+        .await
+        .map_err(InternalServerError)?;
+        if result.rows_affected() == 0 {
+            return Ok(RecoveryCodesApiResponse::Conflict(message(
+                "已开启两步验证",
+            )));
+        }
+        let recovery_codes =
+            recovery_codes::generate(db.get_pool(), uid, middlewares::peppers(request), request)
+                .await?;
+        Ok(RecoveryCodesApiResponse::Ok(Json(RecoveryCodes {
+            recovery_codes,
+        })))
+    }
+
+    /// 重新生成恢复码, 需要当前的验证码, 之前的恢复码随即作废
+    #[oai(
+        path = "/recovery-codes",
+        method = "post",
+        transform = "require_session"
+    )]
+    async fn regenerate_recovery_codes(
+        &self,
+        req: Json<TotpCodeRequest>,
+        request: &Request,
+    ) -> Result<RecoveryCodesApiResponse> {
+        let uid = current_uid(request)?;
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        let state = TotpState::load(db.get_pool(), uid)
+            .await
+            .map_err(InternalServerError)?;
+        if !state.is_enabled() {
+            return Ok(RecoveryCodesApiResponse::Conflict(message(
+                "未开启两步验证",
+            )));
+        }
+        if !state.verify(db.get_pool(), uid, &req.code).await? {
+            return Ok(RecoveryCodesApiResponse::InvalidCode(message(
+                "验证码不正确",
+            )));
+        }
+
+        let recovery_codes =
+            recovery_codes::generate(db.get_pool(), uid, middlewares::peppers(request), request)
+                .await?;
+        Ok(RecoveryCodesApiResponse::Ok(Json(RecoveryCodes {
+            recovery_codes,
+        })))
+    }
+
+    /// 关闭两步验证, 需要当前的验证码
+    #[oai(path = "/totp/disable", method = "post", transform = "require_session")]
+    async fn disable(
+        &self,
+        req: Json<TotpCodeRequest>,
+        request: &Request,
+    ) -> Result<TotpApiResponse> {
+        let uid = current_uid(request)?;
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        let state = TotpState::load(db.get_pool(), uid)
+            .await
+            .map_err(InternalServerError)?;
+        if !state.is_enabled() {
+            return Ok(TotpApiResponse::Conflict(message("未开启两步验证")));
+        }
+        if !state.verify(db.get_pool(), uid, &req.code).await? {
+            return Ok(TotpApiResponse::InvalidCode(message("验证码不正确")));
+        }
+
+        sqlx::query(
+            "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL \
+             WHERE id = ?",
+        )
+        .bind(uid)
+        .execute(db.get_pool())
+        .await
+        .map_err(InternalServerError)?;
+        recovery_codes::delete_all(db.get_pool(), uid)
+            .await
+            .map_err(InternalServerError)?;
+        Ok(TotpApiResponse::Ok)
+    }
+}
+
+/// 登录第二步提交的验证方式
+pub enum SecondFactor<'a> {
+    /// 认证器 app 中的验证码
+    Totp(&'a str),
+    /// 恢复码, 使用后记录到审计日志
+    RecoveryCode(&'a str),
+}
+
+/// 登录第二步, 见 `ApiToken::do_login`
+/// challenge 无效或验证码不正确时返回给用户的原因
+pub async fn verify_login(
+    pool: &Pool<MySql>,
+    keyring: &KeyRing,
+    validation: &Validation,
+    challenge: &str,
+    factor: SecondFactor<'_>,
+    used: &dyn ReplayCache,
+    request: &Request,
+) -> Result<Result<UserInfo, &'static str>> {
+    let cipher = middlewares::field_cipher(request);
+    let user = match redeem_challenge(pool, keyring, validation, challenge, used, cipher).await? {
+        Ok(user) => user,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let state = TotpState::load(pool, user.id)
+        .await
+        .map_err(InternalServerError)?;
+    // challenge 签发后关闭了两步验证
+    if !state.is_enabled() {
+        return Ok(Err("登录已过期, 请重新登录"));
+    }
+    match factor {
+        SecondFactor::Totp(code) => {
+            if !state.verify(pool, user.id, code).await? {
+                return Ok(Err("验证码不正确, 请重新登录"));
+            }
+        }
+        SecondFactor::RecoveryCode(code) => {
+            let peppers = middlewares::peppers(request);
+            if !recovery_codes::redeem(pool, user.id, code, peppers, request).await? {
+                return Ok(Err("恢复码不正确或已使用, 请重新登录"));
+            }
+        }
+    }
+    Ok(Ok(user))
+}
@@ -1,6 +1,19 @@
-use crate::api::token::CurrentUser;
-use poem::http::header::AUTHORIZATION;
-use poem::{Endpoint, Middleware, Request, Result};
+use std::sync::Arc;
+
+use crate::api::current_user::CurrentUser;
+use crate::api::{dpop, reference, scopes};
+use poem::error::InternalServerError;
+use poem::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use poem::http::StatusCode;
+use poem::{Endpoint, Error, Middleware, Request, Response, Result};
+use rc_token::{
+    Claims, KeyRing, ReferenceTokenStore, RegisteredClaims, RevocationStore, Scopes, Validation,
+};
+use rc_utilities::field_encryption::FieldCipher;
+use rc_utilities::password::{HashParams, Peppers};
+
+const BEARER_SCHEME: &str = "Bearer";
+const DPOP_SCHEME: &str = "DPoP";
 
 pub struct JwtMiddleware;
 
@@ -22,22 +35,244 @@ impl<E: Endpoint> Endpoint for JwtMiddlewareImpl<E> {
     type Output = E::Output;
 
     async fn call(&self, mut req: Request) -> Result<Self::Output> {
-        // Just example. You have to make sure your middleware is correct
-        if let Some(token) = req
+        // Bearer 用于普通 token, DPoP 用于绑定了客户端公钥的 token
+        if let Some((scheme, token)) = req
             .headers()
             .get(AUTHORIZATION)
             .and_then(|value| value.to_str().ok())
-            .filter(|value| value.starts_with("Bearer "))
-            .map(|value| &value[7..])
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| *scheme == BEARER_SCHEME || *scheme == DPOP_SCHEME)
+            .map(|(scheme, token)| (scheme.to_string(), token.to_string()))
         {
-            // Decode JWT token
-            let result = rc_token::parse_token::<CurrentUser>("123456", token, true)
-                .expect("中间件-解析token异常");
-            // Attache
-            req.extensions_mut().insert(result.1);
+            // 无效的 token 不附加 CurrentUser, 只记录原因, 由需要登录的接口返回 401
+            // 存储或配置错误不是客户端的问题, 返回 500
+            match authenticate(&req, &scheme, &token).await {
+                Ok(claims) => {
+                    let scopes = claims
+                        .registered
+                        .scope
+                        .clone()
+                        .unwrap_or_else(scopes::default_user_scopes);
+                    // Attache
+                    req.extensions_mut().insert(claims.data);
+                    req.extensions_mut().insert(scopes);
+                    // 注销时需要 token 的 jti 和 exp
+                    req.extensions_mut().insert(claims.registered);
+                }
+                Err(err) if err.is_token_error() => {
+                    req.extensions_mut()
+                        .insert(TokenRejection::new(&scheme, &err));
+                }
+                Err(err) => return Err(InternalServerError(err)),
+            }
         }
 
         // call the next endpoint.
         self.ep.call(req).await
     }
 }
+
+/// 校验 token: 签名, 有效期, 黑名单, 类型和 DPoP 绑定
+/// refresh token 只能用于 /token/refresh, 一次性 token 只能用于邮件链接, 都不能作为访问凭证
+async fn authenticate(
+    req: &Request,
+    scheme: &str,
+    token: &str,
+) -> Result<Claims<CurrentUser>, rc_token::Error> {
+    // Decode JWT token
+    let keyring = keyring(req);
+    let validation = validation(req);
+    let revocation = req
+        .data::<Arc<dyn RevocationStore>>()
+        .cloned()
+        .expect("Token revocation store expected");
+    let references = req
+        .data::<Arc<dyn ReferenceTokenStore>>()
+        .cloned()
+        .expect("Reference token store expected");
+    let claims = reference::decode_unrevoked_token(
+        keyring,
+        token,
+        validation,
+        revocation.as_ref(),
+        references.as_ref(),
+    )
+    .await?;
+    if claims.token_type != rc_token::TokenType::AccessToken {
+        return Err(rc_token::Error::WrongTokenType);
+    }
+    check_binding(req, scheme, token, &claims.registered, keyring).await?;
+    Ok(claims)
+}
+
+/// 启动时加载的 keyring, 见 main.rs
+pub fn keyring(req: &Request) -> &KeyRing {
+    req.data::<Arc<KeyRing>>().expect("Token keyring expected")
+}
+
+/// 启动时加载的 token 校验选项, 见 main.rs
+pub fn validation(req: &Request) -> &Validation {
+    req.data::<Validation>()
+        .expect("Token validation config expected")
+}
+
+/// 启动时加载的密码 pepper, 见 main.rs
+pub fn peppers(req: &Request) -> &Peppers {
+    req.data::<Peppers>().expect("Password peppers expected")
+}
+
+/// 启动时加载的 Argon2id 参数, 见 main.rs
+pub fn hash_params(req: &Request) -> &HashParams {
+    req.data::<HashParams>()
+        .expect("Password hash params expected")
+}
+
+/// 启动时加载的敏感字段加密密钥, 见 main.rs
+pub fn field_cipher(req: &Request) -> &FieldCipher {
+    req.data::<FieldCipher>()
+        .expect("Field encryption keys expected")
+}
+
+/// 绑定了公钥的 token 必须以 DPoP 方式使用并附带匹配的 proof
+/// 未绑定的 token 只能以 Bearer 方式使用
+async fn check_binding(
+    req: &Request,
+    scheme: &str,
+    token: &str,
+    claims: &RegisteredClaims,
+    keyring: &rc_token::KeyRing,
+) -> Result<(), rc_token::Error> {
+    match (&claims.cnf, scheme == DPOP_SCHEME) {
+        (Some(_), true) => match dpop::check_proof(req, Some(token), keyring).await? {
+            Some(proof) => proof.check_binding(claims),
+            None => Err(rc_token::Error::InvalidProof("missing DPoP proof")),
+        },
+        (None, false) => Ok(()),
+        (Some(_), false) => Err(rc_token::Error::InvalidProof(
+            "DPoP bound token used as bearer token",
+        )),
+        (None, true) => Err(rc_token::Error::InvalidProof(
+            "token is not bound to a DPoP key",
+        )),
+    }
+}
+
+/// Why the token of a request was rejected, kept for the 401 response of
+/// endpoints which require login.
+#[derive(Debug, Clone)]
+pub struct TokenRejection {
+    scheme: &'static str,
+    /// OAuth error code (RFC 6750 / RFC 9449)
+    error: &'static str,
+    /// Stable rc-token error code, e.g. `expired`
+    code: &'static str,
+    description: String,
+}
+
+impl TokenRejection {
+    fn new(scheme: &str, err: &rc_token::Error) -> Self {
+        let proof_error = matches!(err, rc_token::Error::InvalidProof(_));
+        TokenRejection {
+            scheme: if scheme == DPOP_SCHEME || proof_error {
+                DPOP_SCHEME
+            } else {
+                BEARER_SCHEME
+            },
+            error: if proof_error {
+                "invalid_dpop_proof"
+            } else {
+                "invalid_token"
+            },
+            code: err.code(),
+            description: err.to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.code {
+            "expired" => "token 已过期, 请刷新或重新登录",
+            "revoked" => "token 已注销, 请重新登录",
+            "not_yet_valid" => "token 尚未生效",
+            "invalid_signature" | "decryption_failed" => "token 签名无效",
+            "unknown_key" => "token 的签名密钥已失效, 请重新登录",
+            "wrong_token_type" => "该 token 不能用于访问接口",
+            "invalid_dpop_proof" => "DPoP proof 无效",
+            "invalid_issuer" | "invalid_audience" => "token 不是签发给本服务的",
+            _ => "token 格式错误",
+        }
+    }
+
+    /// `WWW-Authenticate` header value
+    fn challenge(&self) -> String {
+        // 描述中可能含有 token 里的 kid 等内容, 只保留可以放入 quoted-string 的字符
+        let description: String = self
+            .description
+            .chars()
+            .filter(|c| *c == ' ' || (c.is_ascii_graphic() && !matches!(c, '"' | '\\')))
+            .collect();
+        format!(
+            "{} error=\"{}\", error_description=\"{}\"",
+            self.scheme, self.error, description
+        )
+    }
+
+    fn into_error(self) -> Error {
+        let body = serde_json::json!({
+            "code": -1,
+            "error": self.code,
+            "reason": self.reason(),
+        });
+        Error::from_response(
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(WWW_AUTHENTICATE, self.challenge())
+                .content_type("application/json")
+                .body(body.to_string()),
+        )
+    }
+}
+
+/// Rejects requests whose access token lacks any of the scopes.
+/// 未登录返回 401 (token 无效时带有 `WWW-Authenticate` 说明原因), 缺少 scope 返回 403
+pub struct RequireScopes(pub &'static [&'static str]);
+
+impl<E: Endpoint> Middleware<E> for RequireScopes {
+    type Output = RequireScopesImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RequireScopesImpl { ep, scopes: self.0 }
+    }
+}
+
+pub struct RequireScopesImpl<E> {
+    ep: E,
+    scopes: &'static [&'static str],
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for RequireScopesImpl<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let Some(granted) = req.extensions().get::<Scopes>() else {
+            if let Some(rejection) = req.extensions().get::<TokenRejection>() {
+                return Err(rejection.clone().into_error());
+            }
+            return Err(Error::from_response(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, BEARER_SCHEME)
+                    .body("请先登录"),
+            ));
+        };
+        let missing = granted.missing(self.scopes);
+        if !missing.is_empty() {
+            return Err(Error::from_string(
+                format!("缺少权限: {}", missing.join(" ")),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        self.ep.call(req).await
+    }
+}
@@ -1,10 +1,31 @@
 use poem_openapi::{OpenApi, OpenApiService};
 
+mod account;
+mod audit;
+mod current_user;
+pub mod dpop;
+mod introspection;
+pub mod jwks;
+mod mfa;
 pub mod middlewares;
+mod recovery_codes;
+pub mod reference;
+pub mod revocation;
+mod scopes;
 mod tags;
 mod token;
+mod token_family;
 mod user;
 
 pub fn create_api_service() -> OpenApiService<impl OpenApi, ()> {
-    OpenApiService::new(token::ApiToken, "Love & Dream", env!("CARGO_PKG_VERSION"))
+    OpenApiService::new(
+        (
+            token::ApiToken,
+            introspection::ApiIntrospection,
+            account::ApiAccount,
+            mfa::ApiMfa,
+        ),
+        "Love & Dream",
+        env!("CARGO_PKG_VERSION"),
+    )
 }
@@ -0,0 +1,128 @@
+/**
+ * 恢复码
+ *   开启两步验证时生成一组, 丢失认证器时代替验证码完成登录, 每个只能使用一次
+ *   只保存以 pepper 为密钥的 HMAC-SHA256 (Peppers::keyed_digest), 明文只在生成时返回一次
+ *   恢复码是随机生成的, 不需要 Argon2id. 使用时按用户查出未使用的恢复码, 逐个以常数时间比较
+ *   生成和使用都记录到审计日志
+ */
+use crate::api::audit;
+
+use poem::{error::InternalServerError, Request, Result};
+use rc_utilities::password::Peppers;
+use rc_utilities::random;
+use sqlx::{MySql, Pool};
+
+/// Codes generated at a time.
+pub const COUNT: usize = 10;
+
+/// Replaces all codes of `user_id` with `COUNT` new ones and returns them in
+/// plain text, only their keyed digests are stored.
+pub async fn generate(
+    pool: &Pool<MySql>,
+    user_id: u64,
+    peppers: &Peppers,
+    request: &Request,
+) -> Result<Vec<String>> {
+    let codes = random::recovery_codes(COUNT);
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| peppers.keyed_digest(code))
+        .collect();
+
+    let mut tx = pool.begin().await.map_err(InternalServerError)?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(InternalServerError)?;
+    for hash in hashes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&mut tx)
+            .await
+            .map_err(InternalServerError)?;
+    }
+    audit::record(
+        &mut tx,
+        user_id,
+        audit::RECOVERY_CODES_GENERATED,
+        None,
+        request,
+    )
+    .await
+    .map_err(InternalServerError)?;
+    tx.commit().await.map_err(InternalServerError)?;
+    Ok(codes)
+}
+
+/// Number of unused codes of `user_id`.
+pub async fn remaining(pool: &Pool<MySql>, user_id: u64) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM recovery_codes WHERE user_id = ? AND used_at IS NULL")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+    Ok(count)
+}
+
+/// Uses `input` if it is one of the unused codes of `user_id`. The use is
+/// recorded in the audit log with the number of codes left.
+pub async fn redeem(
+    pool: &Pool<MySql>,
+    user_id: u64,
+    input: &str,
+    peppers: &Peppers,
+    request: &Request,
+) -> Result<bool> {
+    let Some(code) = random::normalize_recovery_code(input) else {
+        return Ok(false);
+    };
+    let unused: Vec<(u64, String)> = sqlx::query_as(
+        "SELECT id, code_hash FROM recovery_codes WHERE user_id = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(InternalServerError)?;
+    let Some(id) = unused
+        .iter()
+        .find(|(_, code_hash)| peppers.check_keyed_digest(&code, code_hash))
+        .map(|(id, _)| *id)
+    else {
+        return Ok(false);
+    };
+
+    let mut tx = pool.begin().await.map_err(InternalServerError)?;
+    // 并发请求中只有一个能使用该恢复码
+    let updated =
+        sqlx::query("UPDATE recovery_codes SET used_at = NOW() WHERE id = ? AND used_at IS NULL")
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .map_err(InternalServerError)?;
+    if updated.rows_affected() == 0 {
+        return Ok(false);
+    }
+    let detail = format!("{} remaining", unused.len() - 1);
+    audit::record(
+        &mut tx,
+        user_id,
+        audit::RECOVERY_CODE_USED,
+        Some(&detail),
+        request,
+    )
+    .await
+    .map_err(InternalServerError)?;
+    tx.commit().await.map_err(InternalServerError)?;
+    Ok(true)
+}
+
+/// Deletes all codes of `user_id`, e.g. when 2FA is disabled.
+pub async fn delete_all(pool: &Pool<MySql>, user_id: u64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
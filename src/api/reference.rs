@@ -0,0 +1,96 @@
+/**
+ * reference token
+ *   TOKEN_FORMAT / REFRESH_TOKEN_FORMAT 为 reference 时签发不含任何数据的随机字符串
+ *   token 的哈希与用户, 设备, 过期时间保存在 mysql 或 redis 中
+ *   中间件和各接口通过这里的函数解析, 同时支持 JWT 和 reference token
+ */
+use std::sync::Arc;
+
+use crate::api::current_user::CurrentUser;
+use rc_token::{
+    Claims, IssuedToken, KeyRing, MemoryReferenceTokenStore, MySqlReferenceTokenStore,
+    RedisReferenceTokenStore, ReferenceTokenStore, RegisteredClaims, RevocationStore, TokenFormat,
+    TokenType, Validation,
+};
+
+/// 按 `TOKEN_REFERENCE_STORE` 创建 reference token 存储: memory, mysql 或 redis
+/// 未配置时与 `TOKEN_REVOCATION_STORE` 相同
+pub async fn store_from_env() -> Result<Arc<dyn ReferenceTokenStore>, Box<dyn std::error::Error>> {
+    let kind = dotenvy::var("TOKEN_REFERENCE_STORE")
+        .or_else(|_| dotenvy::var("TOKEN_REVOCATION_STORE"))
+        .unwrap_or_else(|_| "memory".to_string());
+    let store: Arc<dyn ReferenceTokenStore> = match kind.as_str() {
+        "memory" => Arc::new(MemoryReferenceTokenStore::new()),
+        "mysql" => {
+            let db = rc_database::Database::new().await?;
+            Arc::new(MySqlReferenceTokenStore::new(db.get_pool().clone()))
+        }
+        "redis" => {
+            let url =
+                dotenvy::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+            Arc::new(RedisReferenceTokenStore::open(&url).await?)
+        }
+        _ => return Err(format!("unsupported TOKEN_REFERENCE_STORE `{}`", kind).into()),
+    };
+    Ok(store)
+}
+
+/// 按 keyring 中配置的格式签发 JWT 或 reference token
+pub async fn create_token(
+    keyring: &KeyRing,
+    references: &dyn ReferenceTokenStore,
+    current_user: &CurrentUser,
+    claims: &RegisteredClaims,
+    token_type: TokenType,
+    ttl: rc_token::Duration,
+) -> Result<IssuedToken, rc_token::Error> {
+    match keyring.format(token_type) {
+        TokenFormat::Reference => {
+            rc_token::create_reference_token(
+                keyring,
+                references,
+                current_user,
+                claims,
+                token_type,
+                ttl,
+            )
+            .await
+        }
+        _ => rc_token::create_token(keyring, current_user, claims, token_type, ttl),
+    }
+}
+
+/// 解析 JWT 或 reference token, 不检查黑名单
+pub async fn decode_token(
+    keyring: &KeyRing,
+    token: &str,
+    validation: &Validation,
+    references: &dyn ReferenceTokenStore,
+) -> Result<Claims<CurrentUser>, rc_token::Error> {
+    if rc_token::is_reference_token(token) {
+        rc_token::resolve_reference_token(keyring, references, token, validation).await
+    } else {
+        rc_token::decode_token(keyring, token, validation)
+    }
+}
+
+/// 解析 JWT 或 reference token, 已注销的 token 返回 `Error::Revoked`
+/// 两种 token 都以 jti 注销, 注销接口不需要区分
+pub async fn decode_unrevoked_token(
+    keyring: &KeyRing,
+    token: &str,
+    validation: &Validation,
+    revocation: &dyn RevocationStore,
+    references: &dyn ReferenceTokenStore,
+) -> Result<Claims<CurrentUser>, rc_token::Error> {
+    let claims = decode_token(keyring, token, validation, references).await?;
+    let nonce = claims
+        .registered
+        .jti
+        .as_deref()
+        .ok_or(rc_token::Error::MissingClaim("jti"))?;
+    if revocation.is_revoked(nonce).await? {
+        return Err(rc_token::Error::Revoked);
+    }
+    Ok(claims)
+}
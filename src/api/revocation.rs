@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use rc_token::{
+    MemoryRevocationStore, MySqlRevocationStore, RedisRevocationStore, RevocationStore,
+};
+
+/// 按 `TOKEN_REVOCATION_STORE` 创建 token 黑名单存储: memory (默认), mysql 或 redis
+/// 多实例部署时需要使用 mysql 或 redis, 否则注销只在当前实例生效
+pub async fn store_from_env() -> Result<Arc<dyn RevocationStore>, Box<dyn std::error::Error>> {
+    let kind = dotenvy::var("TOKEN_REVOCATION_STORE").unwrap_or_else(|_| "memory".to_string());
+    let store: Arc<dyn RevocationStore> = match kind.as_str() {
+        "memory" => Arc::new(MemoryRevocationStore::new()),
+        "mysql" => {
+            let db = rc_database::Database::new().await?;
+            Arc::new(MySqlRevocationStore::new(db.get_pool().clone()))
+        }
+        "redis" => {
+            let url =
+                dotenvy::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+            Arc::new(RedisRevocationStore::open(&url).await?)
+        }
+        _ => return Err(format!("unsupported TOKEN_REVOCATION_STORE `{}`", kind).into()),
+    };
+    Ok(store)
+}
@@ -0,0 +1,25 @@
+/**
+ * scope
+ *   access token 中的 scope 决定其可以调用哪些接口
+ *   接口通过 `#[oai(transform = "...")]` 声明所需的 scope, 缺少时返回 403
+ */
+use crate::api::middlewares::RequireScopes;
+
+use poem::{Endpoint, EndpointExt};
+use rc_token::Scopes;
+
+/// 管理自己的登录状态: 注销, 签发权限更小的 token
+pub const SESSION: &str = "session";
+/// 读取自己的用户信息
+pub const USER_READ: &str = "user:read";
+/// 修改自己的用户信息
+pub const USER_WRITE: &str = "user:write";
+
+/// 用户登录时获得的 scope, 也用于引入 scope 之前签发的 token
+pub fn default_user_scopes() -> Scopes {
+    Scopes::new([SESSION, USER_READ, USER_WRITE])
+}
+
+pub fn require_session(ep: impl Endpoint) -> impl Endpoint {
+    ep.with(RequireScopes(&[SESSION]))
+}
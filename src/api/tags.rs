@@ -4,4 +4,8 @@ use poem_openapi::Tags;
 pub enum ApiTags {
     /// Token operations
     Token,
+    /// Account links sent by email
+    Account,
+    /// Two-factor authentication
+    Mfa,
 }
@@ -1,15 +1,25 @@
+use crate::api::current_user::CurrentUser;
+use crate::api::scopes::{self, require_session};
+use crate::api::tags::ApiTags;
+use crate::api::token_family::{self, RotateOutcome};
+use crate::api::user::UserInfo;
 /**
  * login
  *   直接查询数据库, 判断是否存在
  *   不存在则返回无该用户
  *   存在则返回token
  */
-use crate::api::tags::ApiTags;
-use crate::api::user::UserInfo;
+use crate::api::{dpop, mfa, middlewares, reference};
 
+use poem::web::Data;
 use poem::{error::InternalServerError, http::StatusCode, Error, Request, Result};
 use poem_openapi::{payload::Json, types::Example, ApiResponse, Object, OpenApi, Union};
-use serde::{Deserialize, Serialize};
+use rc_token::{
+    Confirmation, KeyRing, ReferenceTokenStore, RegisteredClaims, ReplayCache, RevocationStore,
+    Scopes, TokenPairConfig, TokenType,
+};
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
 
 #[derive(Debug, Object)]
 struct LoginCredentialPassword {
@@ -20,12 +30,36 @@ struct LoginCredentialPassword {
     password: String,
 }
 
+/// Second step of a login with 2FA
+#[derive(Debug, Object)]
+struct LoginCredentialTotp {
+    /// Challenge returned by the password step
+    challenge: String,
+
+    /// Code from the authenticator app
+    code: String,
+}
+
+/// Second step of a login with 2FA, using a recovery code instead of a TOTP code
+#[derive(Debug, Object)]
+struct LoginCredentialRecoveryCode {
+    /// Challenge returned by the password step
+    challenge: String,
+
+    /// One of the recovery codes, e.g. `7K3QD-M9XWA`
+    code: String,
+}
+
 /// Login credential
 #[derive(Debug, Union)]
 #[oai(discriminator_name = "type")]
 enum LoginCredential {
     #[oai(mapping = "password")]
     Password(LoginCredentialPassword),
+    #[oai(mapping = "totp")]
+    Totp(LoginCredentialTotp),
+    #[oai(mapping = "recovery_code")]
+    RecoveryCode(LoginCredentialRecoveryCode),
 }
 
 fn default_device() -> String {
@@ -69,16 +103,61 @@ pub struct LoginResponse {
     token: String,
     /// Refresh token
     refresh_token: String,
-    // The access token expired in seconds
-    // expired_in: i64,
+    /// The access token expires in seconds
+    expires_in: i64,
     /// User info
     user: UserInfo,
 }
 
+/// Refresh request
+#[derive(Debug, Object)]
+struct RefreshRequest {
+    /// Refresh token
+    refresh_token: String,
+}
+
+/// Refresh response
+#[derive(Debug, Object)]
+pub struct RefreshResponse {
+    /// Access token
+    token: String,
+    /// Refresh token, the one in the request can not be used again
+    refresh_token: String,
+    /// The access token expires in seconds
+    expires_in: i64,
+}
+
+/// Logout request
+#[derive(Debug, Object)]
+struct LogoutRequest {
+    /// Refresh token issued together with the current access token
+    refresh_token: Option<String>,
+}
+
+/// Scoped token request
+#[derive(Debug, Object)]
+struct ScopedTokenRequest {
+    /// Space separated scopes, e.g. `user:read`. Scopes the current token does not have are dropped
+    scope: String,
+    /// Lifetime in seconds, at most the configured access token lifetime
+    expires_in: Option<i64>,
+}
+
+/// Scoped token response
+#[derive(Debug, Object)]
+pub struct ScopedTokenResponse {
+    /// Access token
+    token: String,
+    /// Space separated scopes granted to the token
+    scope: String,
+    /// The access token expires in seconds
+    expires_in: i64,
+}
+
 #[derive(Object)]
 pub struct ErrorMessage {
-    code: i32,
-    reason: String,
+    pub code: i32,
+    pub reason: String,
 }
 
 #[derive(ApiResponse)]
@@ -104,12 +183,45 @@ pub enum LoginApiResponse {
     /// Account not associated
     #[oai(status = 410)]
     AccountNotAssociated,
+    /// Invalid DPoP proof
+    #[oai(status = 400)]
+    InvalidDpopProof(Json<ErrorMessage>),
+    /// Password accepted, log in again with the challenge and a second factor
+    #[oai(status = 202)]
+    MfaRequired(Json<mfa::MfaChallenge>),
+}
+
+#[derive(ApiResponse)]
+pub enum RefreshApiResponse {
+    /// Refresh success
+    #[oai(status = 200)]
+    Ok(Json<RefreshResponse>),
+    /// Invalid, expired, reused or revoked refresh token
+    #[oai(status = 401)]
+    InvalidToken(Json<ErrorMessage>),
+    /// Missing or invalid DPoP proof for a DPoP bound refresh token
+    #[oai(status = 400)]
+    InvalidDpopProof(Json<ErrorMessage>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
-pub struct CurrentUser {
-    pub uid: u64,
-    pub device: String,
+#[derive(ApiResponse)]
+pub enum LogoutApiResponse {
+    /// Logout success, the access and refresh tokens are revoked
+    #[oai(status = 200)]
+    Ok,
+    /// Not logged in
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+pub enum ScopedTokenApiResponse {
+    /// Token issued
+    #[oai(status = 200)]
+    Ok(Json<ScopedTokenResponse>),
+    /// None of the requested scopes can be granted
+    #[oai(status = 400)]
+    InvalidScope(Json<ErrorMessage>),
 }
 
 pub struct ApiToken;
@@ -124,64 +236,357 @@ pub struct ApiToken;
 
 #[OpenApi(prefix_path = "/token", tag = "ApiTags::Token")]
 impl ApiToken {
+    /// 请求头附带 DPoP proof 时, 签发的 token 绑定 proof 的公钥
+    /// 开启两步验证的用户密码正确时返回 202 和 challenge, 再以 `totp` 或 `recovery_code` 方式提交 challenge 和验证码
     #[oai(path = "/login", method = "post")]
-    async fn login(&self, req: Json<LoginRequest>, request: &Request) -> Result<LoginApiResponse> {
+    async fn login(
+        &self,
+        req: Json<LoginRequest>,
+        request: &Request,
+        references: Data<&Arc<dyn ReferenceTokenStore>>,
+        used: Data<&Arc<dyn ReplayCache>>,
+        token_config: Data<&TokenPairConfig>,
+    ) -> Result<LoginApiResponse> {
         let cu = request.extensions().get::<CurrentUser>();
         println!("current user: {:?}", cu);
-        self.do_login(req).await
+        let keyring = middlewares::keyring(request);
+        let cnf = match dpop::check_proof(request, None, keyring).await {
+            Ok(proof) => proof.map(|proof| Confirmation { jkt: proof.jkt }),
+            Err(err) => {
+                return Ok(LoginApiResponse::InvalidDpopProof(Json(ErrorMessage {
+                    code: -1,
+                    reason: err.to_string(),
+                })))
+            }
+        };
+        self.do_login(
+            req,
+            request,
+            references.as_ref(),
+            used.as_ref(),
+            &token_config,
+            cnf,
+        )
+        .await
     }
 
-    async fn do_login(&self, req: Json<LoginRequest>) -> Result<LoginApiResponse> {
+    /// 使用 refresh token 换取新的 token, 旧的 refresh token 随即失效
+    /// 绑定了公钥的 refresh token 需要附带同一公钥签名的 DPoP proof
+    #[oai(path = "/refresh", method = "post")]
+    async fn refresh(
+        &self,
+        req: Json<RefreshRequest>,
+        request: &Request,
+        revocation: Data<&Arc<dyn RevocationStore>>,
+        references: Data<&Arc<dyn ReferenceTokenStore>>,
+        token_config: Data<&TokenPairConfig>,
+    ) -> Result<RefreshApiResponse> {
+        let invalid_token = |reason: &str| {
+            Ok(RefreshApiResponse::InvalidToken(Json(ErrorMessage {
+                code: -1,
+                reason: reason.to_string(),
+            })))
+        };
+
+        let keyring = middlewares::keyring(request);
+        let claims = match reference::decode_unrevoked_token(
+            keyring,
+            &req.refresh_token,
+            middlewares::validation(request),
+            revocation.as_ref(),
+            references.as_ref(),
+        )
+        .await
+        {
+            Ok(claims) if claims.token_type == TokenType::RefreshToken => claims,
+            Err(err) if !err.is_token_error() => return Err(InternalServerError(err)),
+            _ => return invalid_token("refresh token 无效或已过期"),
+        };
+        let Some(jti) = claims.registered.jti.clone() else {
+            return invalid_token("refresh token 无效或已过期");
+        };
+        // 在轮换之前校验 proof, 避免无效请求使 refresh token 失效
+        let invalid_proof = |reason: String| {
+            Ok(RefreshApiResponse::InvalidDpopProof(Json(ErrorMessage {
+                code: -1,
+                reason,
+            })))
+        };
+        let cnf = match dpop::check_proof(request, None, keyring).await {
+            Ok(Some(proof)) if claims.registered.cnf.is_some() => {
+                if let Err(err) = proof.check_binding(&claims.registered) {
+                    return invalid_proof(err.to_string());
+                }
+                claims.registered.cnf.clone()
+            }
+            // 未绑定的 refresh token 可在刷新时绑定公钥
+            Ok(Some(proof)) => Some(Confirmation { jkt: proof.jkt }),
+            Ok(None) if claims.registered.cnf.is_some() => {
+                return invalid_proof("缺少 DPoP proof".to_string())
+            }
+            Ok(None) => None,
+            Err(err) => return invalid_proof(err.to_string()),
+        };
+
         let db = rc_database::Database::new()
             .await
             .expect("Database connection expected");
+        let family_id = match token_family::rotate(db.get_pool(), &jti)
+            .await
+            .map_err(InternalServerError)?
+        {
+            RotateOutcome::Rotated { family_id } => family_id,
+            RotateOutcome::Reused => return invalid_token("refresh token 已被使用, 请重新登录"),
+            RotateOutcome::Revoked | RotateOutcome::Unknown => {
+                return invalid_token("refresh token 已失效, 请重新登录")
+            }
+        };
 
-        // 因为使用 enum, 不能直接访问 req.credential.Password.email
-        // 需要通过模式匹配的方式访问数据
-        let (email, password) = match &req.credential {
-            LoginCredential::Password(lcp) => (lcp.email.to_owned(), lcp.password.to_owned()),
+        // 刷新后的 token 保持原有的 scope, 不会扩大权限
+        let scope = claims
+            .registered
+            .scope
+            .unwrap_or_else(scopes::default_user_scopes);
+        let (token, refresh_token) = issue_tokens(
+            db.get_pool(),
+            keyring,
+            references.as_ref(),
+            &token_config,
+            &claims.data,
+            &scope,
+            cnf,
+            Some(&family_id),
+        )
+        .await?;
+        Ok(RefreshApiResponse::Ok(Json(RefreshResponse {
+            token,
+            refresh_token,
+            expires_in: token_config.access_token_ttl().num_seconds(),
+        })))
+    }
+
+    /// 注销: 当前 access token 和对应的 refresh token 加入黑名单, refresh token 家族作废
+    #[oai(path = "/logout", method = "post", transform = "require_session")]
+    async fn logout(
+        &self,
+        req: Json<LogoutRequest>,
+        request: &Request,
+        revocation: Data<&Arc<dyn RevocationStore>>,
+        references: Data<&Arc<dyn ReferenceTokenStore>>,
+    ) -> Result<LogoutApiResponse> {
+        let (Some(current_user), Some(access_claims)) = (
+            request.extensions().get::<CurrentUser>(),
+            request.extensions().get::<RegisteredClaims>(),
+        ) else {
+            return Ok(LogoutApiResponse::Unauthorized);
         };
+        rc_token::revoke_token(access_claims, revocation.as_ref())
+            .await
+            .map_err(InternalServerError)?;
 
-        let user: UserInfo = sqlx::query_as("SELECT * FROM users where email = ?")
-            .bind(email)
-            .fetch_one(db.get_pool())
+        let Some(refresh_token) = &req.refresh_token else {
+            return Ok(LogoutApiResponse::Ok);
+        };
+        // 只注销属于当前用户的 refresh token, 无效或已过期的直接忽略
+        let Ok(claims) = reference::decode_token(
+            middlewares::keyring(request),
+            refresh_token,
+            middlewares::validation(request),
+            references.as_ref(),
+        )
+        .await
+        else {
+            return Ok(LogoutApiResponse::Ok);
+        };
+        if claims.token_type != TokenType::RefreshToken || claims.data.uid != current_user.uid {
+            return Ok(LogoutApiResponse::Ok);
+        }
+        rc_token::revoke_token(&claims.registered, revocation.as_ref())
             .await
-            .map_err(|e| {
-                println!("{:?}", e);
-                LoginApiResponse::UserDoesNotExist
-            })?;
+            .map_err(InternalServerError)?;
+        if let Some(jti) = &claims.registered.jti {
+            let db = rc_database::Database::new()
+                .await
+                .expect("Database connection expected");
+            token_family::revoke_family_of(db.get_pool(), jti)
+                .await
+                .map_err(InternalServerError)?;
+        }
 
-        if !user.check_pw(&password) {
-            return Ok(LoginApiResponse::InvalidAccount(Json(ErrorMessage {
+        Ok(LogoutApiResponse::Ok)
+    }
+
+    /// 签发只含部分 scope 的 access token, 例如交给管理后台或其他服务使用
+    /// 新 token 的 scope 不会超出当前 token, 也不附带 refresh token
+    #[oai(path = "/scoped", method = "post", transform = "require_session")]
+    async fn scoped(
+        &self,
+        req: Json<ScopedTokenRequest>,
+        request: &Request,
+        references: Data<&Arc<dyn ReferenceTokenStore>>,
+        token_config: Data<&TokenPairConfig>,
+    ) -> Result<ScopedTokenApiResponse> {
+        let (Some(current_user), Some(granted), Some(access_claims)) = (
+            request.extensions().get::<CurrentUser>(),
+            request.extensions().get::<Scopes>(),
+            request.extensions().get::<RegisteredClaims>(),
+        ) else {
+            return Err(Error::from_status(StatusCode::UNAUTHORIZED));
+        };
+        let requested: Scopes = req.scope.parse().map_err(InternalServerError)?;
+        let scope = granted.narrow(&requested);
+        if scope.is_empty() {
+            return Ok(ScopedTokenApiResponse::InvalidScope(Json(ErrorMessage {
                 code: -1,
-                reason: "密码不正确,请重新输入".to_string(),
+                reason: "没有可以授予的 scope".to_string(),
             })));
         }
+        let ttl = match req.expires_in {
+            Some(seconds) if seconds > 0 => {
+                rc_token::Duration::seconds(seconds).min(token_config.access_token_ttl())
+            }
+            _ => token_config.access_token_ttl(),
+        };
 
-        let secret_key = dotenvy::var("SECRET_KEY").map_err(InternalServerError)?;
-        let token_expiry_seconds = dotenvy::var("TOKEN_EXPIRY_SECONDS")
-            .map_err(InternalServerError)?
-            .parse::<i64>()
-            .unwrap();
-        let refresh_token_expiry_seconds = dotenvy::var("REFRESH_TOKEN_EXPIRY_SECONDS")
-            .map_err(InternalServerError)?
-            .parse::<i64>()
-            .unwrap();
-
-        let (refresh_token, token) = rc_token::create_token_pair(
-            &secret_key,
-            CurrentUser {
-                uid: user.id,
-                device: "web".to_string(),
-            },
-            token_expiry_seconds,
-            refresh_token_expiry_seconds,
+        let claims = RegisteredClaims {
+            sub: Some(current_user.uid.to_string()),
+            scope: Some(scope.clone()),
+            // 当前 token 绑定了公钥时, 新 token 绑定同一公钥
+            cnf: access_claims.cnf.clone(),
+            ..RegisteredClaims::from_env()
+        };
+        let token = reference::create_token(
+            middlewares::keyring(request),
+            references.as_ref(),
+            current_user,
+            &claims,
+            TokenType::AccessToken,
+            ttl,
         )
+        .await
         .map_err(InternalServerError)?;
 
+        Ok(ScopedTokenApiResponse::Ok(Json(ScopedTokenResponse {
+            token: token.token,
+            scope: scope.to_string(),
+            expires_in: ttl.num_seconds(),
+        })))
+    }
+
+    async fn do_login(
+        &self,
+        req: Json<LoginRequest>,
+        request: &Request,
+        references: &dyn ReferenceTokenStore,
+        used: &dyn ReplayCache,
+        token_config: &TokenPairConfig,
+        cnf: Option<Confirmation>,
+    ) -> Result<LoginApiResponse> {
+        let keyring = middlewares::keyring(request);
+        let validation = middlewares::validation(request);
+        let db = rc_database::Database::new()
+            .await
+            .expect("Database connection expected");
+        let invalid_account = |reason: &str| {
+            Ok(LoginApiResponse::InvalidAccount(Json(ErrorMessage {
+                code: -1,
+                reason: reason.to_string(),
+            })))
+        };
+
+        // 因为使用 enum, 不能直接访问 req.credential.Password.email
+        // 需要通过模式匹配的方式访问数据
+        let user = match &req.credential {
+            LoginCredential::Password(lcp) => {
+                let Some(mut user) = UserInfo::find_by_email(
+                    db.get_pool(),
+                    middlewares::field_cipher(request),
+                    &lcp.email,
+                )
+                .await?
+                else {
+                    return Ok(LoginApiResponse::UserDoesNotExist);
+                };
+
+                if !user
+                    .check_pw(
+                        db.get_pool(),
+                        &lcp.password,
+                        middlewares::peppers(request),
+                        middlewares::hash_params(request),
+                    )
+                    .await?
+                {
+                    return invalid_account("密码不正确,请重新输入");
+                }
+
+                // 开启了两步验证, 需要再提交验证码
+                let totp = mfa::TotpState::load(db.get_pool(), user.id)
+                    .await
+                    .map_err(InternalServerError)?;
+                if totp.is_enabled() {
+                    let challenge =
+                        mfa::create_challenge(db.get_pool(), keyring, &user, token_config).await?;
+                    return Ok(LoginApiResponse::MfaRequired(Json(challenge)));
+                }
+                user
+            }
+            LoginCredential::Totp(lct) => {
+                let factor = mfa::SecondFactor::Totp(&lct.code);
+                match mfa::verify_login(
+                    db.get_pool(),
+                    keyring,
+                    validation,
+                    &lct.challenge,
+                    factor,
+                    used,
+                    request,
+                )
+                .await?
+                {
+                    Ok(user) => user,
+                    Err(reason) => return invalid_account(reason),
+                }
+            }
+            LoginCredential::RecoveryCode(lcr) => {
+                let factor = mfa::SecondFactor::RecoveryCode(&lcr.code);
+                match mfa::verify_login(
+                    db.get_pool(),
+                    keyring,
+                    validation,
+                    &lcr.challenge,
+                    factor,
+                    used,
+                    request,
+                )
+                .await?
+                {
+                    Ok(user) => user,
+                    Err(reason) => return invalid_account(reason),
+                }
+            }
+        };
+
+        let current_user = CurrentUser {
+            uid: user.id,
+            device: "web".to_string(),
+        };
+        let (token, refresh_token) = issue_tokens(
+            db.get_pool(),
+            keyring,
+            references,
+            token_config,
+            &current_user,
+            &scopes::default_user_scopes(),
+            cnf,
+            None,
+        )
+        .await?;
+
         Ok(LoginApiResponse::Ok(Json(LoginResponse {
             token,
             refresh_token,
+            expires_in: token_config.access_token_ttl().num_seconds(),
             user: UserInfo {
                 id: user.id,
                 email: user.email,
@@ -190,7 +595,71 @@ impl ApiToken {
                 created_at: user.created_at,
                 password: "".to_string(),
                 salt: "".to_string(),
+                email_encrypted: None,
+                phone: user.phone,
+                phone_encrypted: None,
+                password_version: 0,
             },
         })))
     }
 }
+
+/// 签发 token 和 refresh token, 并记录 refresh token 所属的家族
+/// family_id 为空时 (登录) 创建新的家族, cnf 不为空时两个 token 都绑定该公钥
+#[allow(clippy::too_many_arguments)]
+async fn issue_tokens(
+    pool: &Pool<MySql>,
+    keyring: &KeyRing,
+    references: &dyn ReferenceTokenStore,
+    token_config: &TokenPairConfig,
+    current_user: &CurrentUser,
+    scope: &Scopes,
+    cnf: Option<Confirmation>,
+    family_id: Option<&str>,
+) -> Result<(String, String)> {
+    let claims = rc_token::RegisteredClaims {
+        sub: Some(current_user.uid.to_string()),
+        scope: Some(scope.clone()),
+        cnf,
+        ..rc_token::RegisteredClaims::from_env()
+    };
+    let refresh_token = reference::create_token(
+        keyring,
+        references,
+        current_user,
+        &claims,
+        TokenType::RefreshToken,
+        token_config.refresh_token_ttl(),
+    )
+    .await
+    .map_err(InternalServerError)?;
+    let token = reference::create_token(
+        keyring,
+        references,
+        current_user,
+        &claims,
+        TokenType::AccessToken,
+        token_config.access_token_ttl(),
+    )
+    .await
+    .map_err(InternalServerError)?;
+
+    let jti = refresh_token.claims.jti.as_deref().unwrap_or_default();
+    let expires_at = token_family::expires_at(keyring, refresh_token.claims.exp);
+    match family_id {
+        Some(family_id) => token_family::add_token(pool, family_id, jti, expires_at).await,
+        None => {
+            token_family::create_family(
+                pool,
+                jti,
+                current_user.uid,
+                &current_user.device,
+                expires_at,
+            )
+            .await
+        }
+    }
+    .map_err(InternalServerError)?;
+
+    Ok((token.token, refresh_token.token))
+}
@@ -0,0 +1,135 @@
+/**
+ * refresh token 家族
+ *   每次登录创建一个家族, 之后每次刷新签发的 refresh token 都属于同一个家族
+ *   每个 refresh token 只能使用一次, 旧 token 被再次使用说明已泄露, 整个家族作废
+ */
+use rc_token::KeyRing;
+use sqlx::types::chrono::{DateTime, TimeZone, Utc};
+use sqlx::{MySql, Pool};
+
+/// Result of trying to use a refresh token.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RotateOutcome {
+    /// The token was unused and is now consumed; issue the next one in `family_id`.
+    Rotated { family_id: String },
+    /// The token was already used. The whole family has been revoked.
+    Reused,
+    /// The family was revoked earlier (reuse, logout ...).
+    Revoked,
+    /// No such token was ever issued by us.
+    Unknown,
+}
+
+/// Expiry of a refresh token, now (by the keyring's clock) if it has no `exp`.
+pub fn expires_at(keyring: &KeyRing, exp: Option<i64>) -> DateTime<Utc> {
+    exp.and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+        .unwrap_or_else(|| keyring.now())
+}
+
+/// Starts a new family with its first refresh token. The family id is the
+/// jti of that first token.
+pub async fn create_family(
+    pool: &Pool<MySql>,
+    jti: &str,
+    user_id: u64,
+    device: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO token_families (id, user_id, device) VALUES (?, ?, ?)")
+        .bind(jti)
+        .bind(user_id)
+        .bind(device)
+        .execute(pool)
+        .await?;
+    add_token(pool, jti, jti, expires_at).await
+}
+
+pub async fn add_token(
+    pool: &Pool<MySql>,
+    family_id: &str,
+    jti: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO refresh_tokens (jti, family_id, expires_at) VALUES (?, ?, ?)")
+        .bind(jti)
+        .bind(family_id)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Marks the refresh token `jti` as used, detecting reuse.
+pub async fn rotate(pool: &Pool<MySql>, jti: &str) -> Result<RotateOutcome, sqlx::Error> {
+    let row: Option<(String, Option<DateTime<Utc>>)> = sqlx::query_as(
+        "SELECT f.id, f.revoked_at FROM refresh_tokens t \
+         JOIN token_families f ON f.id = t.family_id WHERE t.jti = ?",
+    )
+    .bind(jti)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((family_id, revoked_at)) = row else {
+        return Ok(RotateOutcome::Unknown);
+    };
+    if revoked_at.is_some() {
+        return Ok(RotateOutcome::Revoked);
+    }
+
+    // 条件更新保证并发请求中只有一个能使用该 token
+    let updated =
+        sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE jti = ? AND used_at IS NULL")
+            .bind(jti)
+            .execute(pool)
+            .await?;
+    if updated.rows_affected() == 0 {
+        revoke_family(pool, &family_id).await?;
+        return Ok(RotateOutcome::Reused);
+    }
+
+    Ok(RotateOutcome::Rotated { family_id })
+}
+
+/// Whether the refresh token `jti` can still be used: issued by us, not used
+/// yet and its family not revoked.
+pub async fn is_active(pool: &Pool<MySql>, jti: &str) -> Result<bool, sqlx::Error> {
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT t.jti FROM refresh_tokens t JOIN token_families f ON f.id = t.family_id \
+         WHERE t.jti = ? AND t.used_at IS NULL AND f.revoked_at IS NULL",
+    )
+    .bind(jti)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
+
+pub async fn revoke_family(pool: &Pool<MySql>, family_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE token_families SET revoked_at = NOW() WHERE id = ? AND revoked_at IS NULL")
+        .bind(family_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Revokes the family the refresh token `jti` belongs to, e.g. on logout.
+pub async fn revoke_family_of(pool: &Pool<MySql>, jti: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE token_families f JOIN refresh_tokens t ON f.id = t.family_id \
+         SET f.revoked_at = NOW() WHERE t.jti = ? AND f.revoked_at IS NULL",
+    )
+    .bind(jti)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Revokes all families of a user, e.g. after a password reset.
+pub async fn revoke_user_families(pool: &Pool<MySql>, user_id: u64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE token_families SET revoked_at = NOW() WHERE user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -1,6 +1,10 @@
+use poem::{error::InternalServerError, Result};
 use poem_openapi::Object;
+use rc_utilities::field_encryption::{FieldCipher, FieldError};
+use rc_utilities::password::{HashParams, Peppers};
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono;
+use sqlx::{MySql, Pool};
 
 /// User info
 #[derive(Debug, Object, Clone, sqlx::FromRow, Serialize, Deserialize)]
@@ -16,15 +20,274 @@ pub struct UserInfo {
     #[oai(skip)]
     pub salt: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// 加密后的邮箱, 查询时解密到 email
+    #[oai(skip)]
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub email_encrypted: Option<String>,
+    #[sqlx(default)]
+    pub phone: Option<String>,
+    /// 加密后的手机号, 查询时解密到 phone
+    #[oai(skip)]
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub phone_encrypted: Option<String>,
+    /// 密码版本, 只在修改密码时加一, 重新计算哈希时不变
+    #[oai(skip)]
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub password_version: u32,
+}
+
+/// 邮箱加密和 blind index 使用的列名, 同时作为 AES-GCM 的附加数据
+pub const EMAIL_COLUMN: &str = "users.email";
+/// 手机号加密和 blind index 使用的列名, 对应 phone_encrypted 和 phone_index 列
+pub const PHONE_COLUMN: &str = "users.phone";
+
+/// 计算 blind index 前统一大小写和首尾空白, 与原来按 MySQL 默认排序规则查询的结果一致
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// 计算 blind index 前去掉空格, 连字符和括号等分隔符, 只保留数字和 `+`
+pub fn normalize_phone(phone: &str) -> String {
+    phone
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '+')
+        .collect()
+}
+
+/// 敏感字段写入数据库的三列: 明文, 密文和 blind index
+/// 配置了 FIELD_ENCRYPTION_KEYS 时只保存密文和 blind index, 明文列为空; 未配置时只保存明文
+pub struct PiiColumns {
+    pub plain: Option<String>,
+    pub encrypted: Option<String>,
+    pub index: Option<String>,
+}
+
+impl PiiColumns {
+    /// normalized 为计算 blind index 使用的值, 如 `normalize_email(value)`
+    pub fn new(
+        cipher: &FieldCipher,
+        column: &str,
+        value: &str,
+        normalized: &str,
+    ) -> Result<Self, FieldError> {
+        if !cipher.is_enabled() {
+            return Ok(PiiColumns {
+                plain: Some(value.to_string()),
+                encrypted: None,
+                index: None,
+            });
+        }
+        Ok(PiiColumns {
+            plain: None,
+            encrypted: Some(cipher.encrypt(column, value)?),
+            index: Some(cipher.blind_index(column, normalized)?),
+        })
+    }
+
+    pub fn email(cipher: &FieldCipher, email: &str) -> Result<Self, FieldError> {
+        PiiColumns::new(cipher, EMAIL_COLUMN, email, &normalize_email(email))
+    }
+
+    /// 值为空时三列都为空
+    fn empty() -> Self {
+        PiiColumns {
+            plain: None,
+            encrypted: None,
+            index: None,
+        }
+    }
+
+    pub fn phone(cipher: &FieldCipher, phone: &str) -> Result<Self, FieldError> {
+        PiiColumns::new(cipher, PHONE_COLUMN, phone, &normalize_phone(phone))
+    }
+}
+
+/// 解密一列到 plain, 返回是否需要重新加密: 配置了加密但仍为明文, 或使用轮换前的密钥加密
+fn decrypt_column(
+    cipher: &FieldCipher,
+    column: &str,
+    plain: &mut Option<String>,
+    encrypted: &Option<String>,
+) -> Result<bool, FieldError> {
+    match encrypted {
+        Some(encrypted) => {
+            *plain = Some(cipher.decrypt(column, encrypted)?);
+            Ok(cipher.needs_reencrypt(encrypted))
+        }
+        None => Ok(cipher.is_enabled() && plain.is_some()),
+    }
 }
 
 impl UserInfo {
-    pub fn set_password(&mut self, password: String) {
-        let user_pw = rc_utilities::password::generate_pw(&password, &self.salt);
-        self.password = user_pw;
+    /// 一次性 token 绑定的凭据版本, 修改密码后已发出的链接和 challenge 失效
+    pub fn credential_version(&self) -> String {
+        self.password_version.to_string()
+    }
+
+    /// 按 id 查询用户, 邮箱已解密
+    pub async fn find_by_id(
+        pool: &Pool<MySql>,
+        cipher: &FieldCipher,
+        id: u64,
+    ) -> Result<Option<UserInfo>> {
+        let user: Option<UserInfo> = sqlx::query_as("SELECT * FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .map_err(InternalServerError)?;
+        match user {
+            Some(mut user) => {
+                user.decrypt_pii(pool, cipher).await?;
+                Ok(Some(user))
+            }
+            None => Ok(None),
+        }
     }
 
-    pub fn check_pw(&self, input_pw: &str) -> bool {
-        rc_utilities::password::check_pw(input_pw, &self.salt, &self.password)
+    /// 按邮箱查询用户, 邮箱已解密
+    /// 配置了加密时按 blind index 精确匹配, 尚未加密的行仍按明文匹配
+    pub async fn find_by_email(
+        pool: &Pool<MySql>,
+        cipher: &FieldCipher,
+        email: &str,
+    ) -> Result<Option<UserInfo>> {
+        let user: Option<UserInfo> = if cipher.is_enabled() {
+            let index = cipher
+                .blind_index(EMAIL_COLUMN, &normalize_email(email))
+                .map_err(InternalServerError)?;
+            sqlx::query_as(
+                "SELECT * FROM users WHERE email_index = ? OR (email_index IS NULL AND email = ?)",
+            )
+            .bind(index)
+            .bind(email)
+            .fetch_optional(pool)
+            .await
+        } else {
+            sqlx::query_as("SELECT * FROM users WHERE email = ?")
+                .bind(email)
+                .fetch_optional(pool)
+                .await
+        }
+        .map_err(InternalServerError)?;
+        match user {
+            Some(mut user) => {
+                user.decrypt_pii(pool, cipher).await?;
+                Ok(Some(user))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 解密邮箱和手机号. 明文保存的, 或使用轮换前密钥加密的会重新加密并保存
+    /// 解密或加密失败返回 500; 保存失败只记录日志, 不影响本次请求, 下次查询时再试
+    async fn decrypt_pii(&mut self, pool: &Pool<MySql>, cipher: &FieldCipher) -> Result<()> {
+        let upgrade_email =
+            decrypt_column(cipher, EMAIL_COLUMN, &mut self.email, &self.email_encrypted)
+                .map_err(InternalServerError)?;
+        let upgrade_phone =
+            decrypt_column(cipher, PHONE_COLUMN, &mut self.phone, &self.phone_encrypted)
+                .map_err(InternalServerError)?;
+        if !upgrade_email && !upgrade_phone {
+            return Ok(());
+        }
+
+        let email = match &self.email {
+            Some(email) => PiiColumns::email(cipher, email).map_err(InternalServerError)?,
+            None => PiiColumns::empty(),
+        };
+        let phone = match &self.phone {
+            Some(phone) => PiiColumns::phone(cipher, phone).map_err(InternalServerError)?,
+            None => PiiColumns::empty(),
+        };
+        let result = sqlx::query(
+            "UPDATE users SET email = NULL, email_encrypted = ?, email_index = ?, \
+             phone = NULL, phone_encrypted = ?, phone_index = ? WHERE id = ?",
+        )
+        .bind(&email.encrypted)
+        .bind(&email.index)
+        .bind(&phone.encrypted)
+        .bind(&phone.index)
+        .bind(self.id)
+        .execute(pool)
+        .await;
+        match result {
+            Ok(_) => {
+                self.email_encrypted = email.encrypted;
+                self.phone_encrypted = phone.encrypted;
+            }
+            Err(e) => {
+                tracing::warn!(user_id = self.id, error = %e, "unable to save re-encrypted PII")
+            }
+        }
+        Ok(())
+    }
+
+    /// 使用 Argon2id 和当前的 pepper 计算新密码的哈希, 参数见 `PASSWORD_HASH_*`, `PASSWORD_PEPPERS`
+    /// Argon2id 耗时且占用较多内存, 在阻塞线程池中计算, 不占用处理请求的线程
+    pub async fn set_password(
+        &mut self,
+        password: String,
+        peppers: &Peppers,
+        params: &HashParams,
+    ) -> Result<()> {
+        let (peppers, params) = (peppers.clone(), *params);
+        self.password = tokio::task::spawn_blocking(move || peppers.hash_pw(&password, &params))
+            .await
+            .map_err(InternalServerError)?
+            .map_err(InternalServerError)?;
+        Ok(())
+    }
+
+    /// 校验密码. 校验成功时, 旧的 SHA3 哈希, 参数已调整或 pepper 已轮换的哈希会重新计算并保存
+    /// 重新计算或保存失败只记录日志, 不影响本次登录, 下次登录时再试. 与 set_password 相同, 哈希在阻塞线程池中计算
+    pub async fn check_pw(
+        &mut self,
+        pool: &Pool<MySql>,
+        input_pw: &str,
+        peppers: &Peppers,
+        params: &HashParams,
+    ) -> Result<bool> {
+        let (checker, input, salt, user_pw) = (
+            peppers.clone(),
+            input_pw.to_string(),
+            self.salt.clone(),
+            self.password.clone(),
+        );
+        let matched =
+            tokio::task::spawn_blocking(move || checker.check_pw(&input, &salt, &user_pw))
+                .await
+                .map_err(InternalServerError)?;
+        if !matched || !peppers.needs_rehash(&self.password, params) {
+            return Ok(matched);
+        }
+
+        let (peppers, params, input) = (peppers.clone(), *params, input_pw.to_string());
+        let user_pw = match tokio::task::spawn_blocking(move || peppers.hash_pw(&input, &params))
+            .await
+            .map_err(InternalServerError)?
+        {
+            Ok(user_pw) => user_pw,
+            Err(e) => {
+                tracing::warn!(user_id = self.id, error = %e, "unable to rehash password");
+                return Ok(true);
+            }
+        };
+        // 只在密码未被同时修改时更新
+        let result = sqlx::query("UPDATE users SET password = ? WHERE id = ? AND password = ?")
+            .bind(&user_pw)
+            .bind(self.id)
+            .bind(&self.password)
+            .execute(pool)
+            .await;
+        match result {
+            Ok(_) => self.password = user_pw,
+            Err(e) => {
+                tracing::warn!(user_id = self.id, error = %e, "unable to save rehashed password")
+            }
+        }
+        Ok(true)
     }
 }
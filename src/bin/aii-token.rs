@@ -0,0 +1,201 @@
+//! aii-token: 签发和检查 token, 用于排查登录问题
+//!
+//! 密钥, 有效期等配置与服务相同, 从环境变量或 `.env` 读取
+//! 只能签发 access token. refresh token 必须属于 token_families 中的家族, 请通过登录获取
+//!
+//! ```text
+//! aii-token mint --uid 13 [--device web] [--expires-in 3600] [--scope "session user:read"]
+//! aii-token decode <token | -> [--jwks <path>]
+//! ```
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::ExitCode;
+
+use rc_token::{
+    Duration, JwkSet, KeyRing, RegisteredClaims, Scopes, TokenPairConfig, TokenType, Validation,
+};
+use serde_json::{json, Value};
+use sqlx::types::chrono::{TimeZone, Utc};
+
+#[path = "../api/current_user.rs"]
+mod current_user;
+
+use current_user::CurrentUser;
+
+const USAGE: &str = "usage:
+  aii-token mint --uid <uid> [--device <device>] [--expires-in <seconds>] [--scope <scopes>]
+  aii-token decode <token | -> [--jwks <path>]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("mint") => mint(&args[1..]),
+        Some("decode") => decode(&args[1..]).await,
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(output) => {
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// 解析 `--name value` 形式的参数, 其余为位置参数
+fn parse_args(args: &[String]) -> Result<(Vec<&str>, HashMap<&str, &str>), String> {
+    let mut positional = Vec::new();
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for --{}\n{}", name, USAGE))?;
+                options.insert(name, value.as_str());
+            }
+            None => positional.push(arg.as_str()),
+        }
+    }
+    Ok((positional, options))
+}
+
+fn mint(args: &[String]) -> Result<Value, String> {
+    let (_, options) = parse_args(args)?;
+    let uid = options
+        .get("uid")
+        .ok_or_else(|| format!("--uid is required\n{}", USAGE))?
+        .parse::<u64>()
+        .map_err(|_| "--uid must be a number".to_string())?;
+    // 没有登记家族的 refresh token 无法轮换, 也无法通过 introspection
+    let token_type = match options.get("type").copied().unwrap_or("access") {
+        "access" => TokenType::AccessToken,
+        "refresh" => {
+            return Err(
+                "refresh tokens must belong to a token family, log in to get one".to_string(),
+            )
+        }
+        other => return Err(format!("unsupported token type `{}`", other)),
+    };
+    let ttl = match options.get("expires-in") {
+        Some(seconds) => Duration::seconds(
+            seconds
+                .parse()
+                .map_err(|_| "--expires-in must be a number of seconds".to_string())?,
+        ),
+        None => TokenPairConfig::from_env()
+            .map_err(|err| err.to_string())?
+            .ttl(token_type),
+    };
+    // 默认与登录时相同的 scope
+    let scope: Scopes = options
+        .get("scope")
+        .copied()
+        .unwrap_or("session user:read user:write")
+        .parse()
+        .map_err(|err: rc_token::Error| err.to_string())?;
+
+    let keyring = KeyRing::from_env().map_err(|err| err.to_string())?;
+    let user = CurrentUser {
+        uid,
+        device: options.get("device").copied().unwrap_or("web").to_string(),
+    };
+    let claims = RegisteredClaims {
+        sub: Some(uid.to_string()),
+        scope: Some(scope),
+        ..RegisteredClaims::from_env()
+    };
+    // reference token 需要服务的存储, 这里只能签发 JWT
+    let issued = rc_token::create_token(&keyring, &user, &claims, token_type, ttl)
+        .map_err(|err| format!("unable to mint token: {}", err))?;
+
+    Ok(json!({
+        "token": issued.token,
+        "format": keyring.format(token_type).as_str(),
+        "token_type": token_type,
+        "data": user,
+        "claims": issued.claims,
+        "expires_at": timestamp(issued.claims.exp),
+    }))
+}
+
+async fn decode(args: &[String]) -> Result<Value, String> {
+    let (positional, options) = parse_args(args)?;
+    let token = match positional.first() {
+        Some(&"-") | None => {
+            let mut token = String::new();
+            std::io::stdin()
+                .read_to_string(&mut token)
+                .map_err(|err| err.to_string())?;
+            token
+        }
+        Some(token) => token.to_string(),
+    };
+    let token = token.trim();
+
+    let keyring = match options.get("jwks") {
+        Some(location) => {
+            let jwks = JwkSet::load(location)
+                .await
+                .map_err(|err| err.to_string())?;
+            KeyRing::from_jwks(&jwks).map_err(|err| err.to_string())?
+        }
+        None => KeyRing::from_env().map_err(|err| err.to_string())?,
+    };
+    let validation = Validation::from_env().map_err(|err| err.to_string())?;
+
+    let parts = rc_token::inspect_token(token).map_err(|err| err.to_string())?;
+    let mut output = json!({
+        "format": parts.format.as_str(),
+        "header": parts.header,
+    });
+    if rc_token::is_reference_token(token) {
+        output["valid"] = json!(null);
+        output["note"] =
+            json!("reference tokens are stored by the server, use /api/token/introspect");
+        return Ok(output);
+    }
+
+    // 先按服务的规则完整校验, 再忽略时间检查解码一次, 过期的 token 也能查看内容
+    let verified = rc_token::decode_token::<Value>(&keyring, token, &validation);
+    output["valid"] = json!(verified.is_ok());
+    if let Err(err) = &verified {
+        output["error"] = json!({"code": err.code(), "description": err.to_string()});
+    }
+    let lenient = Validation {
+        validate_exp: false,
+        validate_nbf: false,
+        ..validation
+    };
+    match rc_token::decode_token::<Value>(&keyring, token, &lenient) {
+        Ok(claims) => {
+            let now = keyring.now().timestamp();
+            output["verified"] = json!(true);
+            output["token_type"] = json!(claims.token_type);
+            output["data"] = claims.data;
+            output["expires_at"] = json!(timestamp(claims.registered.exp));
+            output["expired"] = json!(claims.registered.exp.is_some_and(|exp| exp <= now));
+            output["expires_in"] = json!(claims.registered.exp.map(|exp| exp - now));
+            output["claims"] = json!(claims.registered);
+        }
+        // 签名无效时只能输出未经校验的内容
+        Err(_) => {
+            output["verified"] = json!(false);
+            output["unverified_payload"] = json!(parts.payload);
+        }
+    }
+    output["note"] = json!("revocation is not checked, use /api/token/introspect");
+    Ok(output)
+}
+
+fn timestamp(seconds: Option<i64>) -> Option<String> {
+    seconds
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .map(|time| time.to_rfc3339())
+}
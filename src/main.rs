@@ -1,17 +1,66 @@
-use poem::{listener::TcpListener, EndpointExt, Route, Server};
+use std::sync::Arc;
+
+use poem::{get, listener::TcpListener, EndpointExt, Route, Server};
 
 mod api;
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
+    // 日志输出到标准输出, 默认 INFO 级别
+    tracing_subscriber::fmt::init();
+
     let api_service = api::create_api_service().server("http://0.0.0.0:3000/api");
     // 开启Swagger UI
     let ui = api_service.swagger_ui();
 
+    let revocation = api::revocation::store_from_env()
+        .await
+        .expect("Token revocation store expected");
+
+    let replay_cache = api::dpop::replay_cache_from_env()
+        .await
+        .expect("DPoP replay cache expected");
+
+    let references = api::reference::store_from_env()
+        .await
+        .expect("Reference token store expected");
+
+    // 密钥 (包括 PEM 文件) 和校验选项只在启动时加载一次, 所有请求共享
+    // 签发和校验 token 都使用 keyring 的时钟, 需要其他时钟时在此调用 with_clock
+    let keyring = Arc::new(rc_token::KeyRing::from_env().expect("Token keyring expected"));
+    let validation = rc_token::Validation::from_env().expect("Token validation config expected");
+
+    // token 有效期只在启动时读取一次
+    let token_config = rc_token::TokenPairConfig::from_env().expect("Token config expected");
+
+    // 泄露密码列表较大, 只在启动时加载一次
+    let password_policy = rc_utilities::password_policy::PasswordPolicy::from_env()
+        .expect("Password policy expected");
+
+    // pepper (包括 PASSWORD_PEPPERS_FILE) 和 Argon2id 参数只在启动时读取一次
+    let peppers = rc_utilities::password::Peppers::from_env().expect("Password peppers expected");
+    let hash_params =
+        rc_utilities::password::HashParams::from_env().expect("Password hash params expected");
+
+    // 敏感字段加密的密钥只在启动时读取一次, 未配置时不加密
+    let field_cipher = rc_utilities::field_encryption::FieldCipher::from_env()
+        .expect("Field encryption keys expected");
+
     let app = Route::new()
         .nest("/api", api_service)
         .nest("/doc", ui)
-        .with(api::middlewares::JwtMiddleware);
+        .at("/.well-known/jwks.json", get(api::jwks::jwks))
+        .with(api::middlewares::JwtMiddleware)
+        .data(revocation)
+        .data(replay_cache)
+        .data(references)
+        .data(keyring)
+        .data(validation)
+        .data(token_config)
+        .data(password_policy)
+        .data(peppers)
+        .data(hash_params)
+        .data(field_cipher);
 
     Server::new(TcpListener::bind("0.0.0.0:3000"))
         .run(app)